@@ -0,0 +1,197 @@
+//! Handling of missing assets: failed loads are reported with their paths, ship models that fail
+//! are replaced with cones and a skybox that fails with a generated starfield. `check_assets`
+//! looks for everything a scenario refers to up front.
+
+use crate::*;
+use crate::ron_asset::*;
+use bevy::{
+    asset::{AssetPath, UntypedAssetLoadFailedEvent},
+    render::{
+        mesh::PrimitiveTopology,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    utils::HashSet,
+};
+use rand::rngs::StdRng;
+
+/// Width of each face of the generated starfield, in pixels.
+const STARFIELD_FACE_SIZE: u32 = 512;
+const STARS_PER_FACE: usize = 600;
+
+/// Paths of the assets that failed to load, without labels, as a failed file takes all the assets
+/// in it down with it.
+#[derive(Resource, Default)]
+pub struct FailedAssets {
+    paths: HashSet<AssetPath<'static>>,
+}
+
+impl FailedAssets {
+    pub fn contains<A: Asset>(&self, handle: &Handle<A>) -> bool {
+        handle.path().is_some_and(|path| self.paths.contains(&path.without_label().into_owned()))
+    }
+}
+
+#[derive(Resource)]
+struct PlaceholderAssets {
+    ship_mesh: Handle<Mesh>,
+    ship_material: Handle<StandardMaterial>,
+}
+
+pub struct AssetFallbackPlugin;
+
+impl Plugin for AssetFallbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FailedAssets>()
+            .add_systems(Startup, create_placeholders)
+            .add_systems(Update, (report_failed_assets, replace_failed_ship_models).chain());
+    }
+}
+
+fn create_placeholders(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>) {
+    commands.insert_resource(PlaceholderAssets {
+        ship_mesh: meshes.add(cone_mesh(16)),
+        // white, so that it takes on the faction colour when tinted
+        ship_material: materials.add(Color::WHITE),
+    });
+}
+
+fn report_failed_assets(mut events: EventReader<UntypedAssetLoadFailedEvent>, mut failed: ResMut<FailedAssets>) {
+    for event in events.read() {
+        error!("asset {} failed to load: {}", event.path, event.error);
+        failed.paths.insert(event.path.without_label().into_owned());
+    }
+}
+
+/// Swaps ship models whose scenes failed to load for cones the size of the ship. The cones are
+/// tinted like any other model, as the ships are still untinted.
+fn replace_failed_ship_models(
+    mut commands: Commands,
+    failed: Res<FailedAssets>,
+    placeholders: Res<PlaceholderAssets>,
+    models: Query<(Entity, &Handle<Scene>, &Parent), With<UnadjustedAABB>>,
+    radii: Query<&Radius>,
+) {
+    for (model, scene, parent) in &models {
+        if !failed.contains(scene) {
+            continue;
+        }
+        let radius = radii.get(parent.get()).map_or(1.0, |radius| radius.radius);
+        commands.entity(model).remove::<(Handle<Scene>, UnadjustedAABB)>().insert((
+            placeholders.ship_mesh.clone(),
+            placeholders.ship_material.clone(),
+            Transform::from_scale(Vec3::splat(radius)),
+        ));
+    }
+}
+
+/// A flat shaded cone of length 2 pointing along +z, the way ships fly, with its base at z = -1.
+fn cone_mesh(segments: usize) -> Mesh {
+    const BASE_RADIUS: f32 = 0.6;
+    let tip = Vec3::Z;
+    let base_center = -Vec3::Z;
+    let rim = |i: usize| {
+        let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+        Vec3::new(angle.cos() * BASE_RADIUS, angle.sin() * BASE_RADIUS, -1.0)
+    };
+    let mut positions = Vec::with_capacity(segments * 6);
+    let mut normals = Vec::with_capacity(segments * 6);
+    for i in 0..segments {
+        let (a, b) = (rim(i), rim(i + 1));
+        let side_normal = (a - tip).cross(b - tip).normalize();
+        positions.extend([tip, a, b, base_center, b, a]);
+        normals.extend([side_normal, side_normal, side_normal, -Vec3::Z, -Vec3::Z, -Vec3::Z]);
+    }
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+}
+
+/// A cubemap of randomly scattered stars, for when the skybox image fails to load. It draws from
+/// its own fixed seed, not the `SimRng`, so that it looks the same every time and doesn't affect
+/// the simulation.
+pub fn starfield_cubemap() -> Image {
+    let size = STARFIELD_FACE_SIZE;
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut data = vec![0u8; (size * size * 6 * 4) as usize];
+    for pixel in data.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+    for _ in 0..STARS_PER_FACE * 6 {
+        let (x, y) = (rng.gen_range(0..size), rng.gen_range(0..size * 6));
+        // most stars are faint, a few are bright, and some are tinted blue or red
+        let brightness = rng.gen::<f32>().powi(3);
+        let warmth = rng.gen_range(-0.2..0.2);
+        let color = [1.0 + warmth, 1.0, 1.0 - warmth].map(|c: f32| (c * brightness * 255.0).min(255.0) as u8);
+        let offset = ((y * size + x) * 4) as usize;
+        data[offset..offset + 3].copy_from_slice(&color);
+    }
+    let mut image = Image::new(
+        Extent3d {
+            width: size,
+            height: size * 6,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.reinterpret_stacked_2d_as_array(6);
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::Cube),
+        ..default()
+    });
+    image
+}
+
+/// Checks that the flocking parameters, the scenario at `scenario_path` and its ship classes
+/// parse, and that every model and image they refer to is in the assets directory. Reports each
+/// problem on stderr, and returns whether there were none.
+pub fn check_assets(scenario_path: &str) -> bool {
+    let mut problems = Vec::new();
+    // files that are only loaded later, by path, and what refers to them
+    let mut referenced: Vec<(String, String)> = Vec::new();
+
+    if let Err(err) = load_ron_file::<FlockingParams>(DEFAULT_FLOCKING_PARAMS) {
+        problems.push(format!("{}: {}", DEFAULT_FLOCKING_PARAMS, err));
+    }
+    match load_ron_file::<Scenario>(scenario_path) {
+        Ok(scenario) => {
+            for name in scenario.class_names() {
+                let class_path = ShipClass::path(name);
+                match load_ron_file::<ShipClass>(&class_path) {
+                    Ok(class) => {
+                        if let Some(problem) = class.radius_problem() {
+                            problems.push(format!("{}: {}", class_path, problem));
+                        }
+                        referenced.push((class.model.scene, class_path));
+                    }
+                    Err(err) => problems.push(format!("{}: {}", class_path, err)),
+                }
+            }
+            if let Some(skybox) = scenario.skybox {
+                referenced.push((skybox.image, scenario_path.to_string()));
+            }
+            for body in scenario.bodies {
+                referenced.push((body.model, scenario_path.to_string()));
+            }
+        }
+        Err(err) => problems.push(format!("{}: {}", scenario_path, err)),
+    }
+    for (path, referrer) in &referenced {
+        if !asset_file_path(path).is_file() {
+            problems.push(format!("{}: missing, referenced by {}", path, referrer));
+        }
+    }
+
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("all assets of {} are present", scenario_path);
+    } else {
+        eprintln!("{} problem(s) with the assets of {}", problems.len(), scenario_path);
+    }
+    problems.is_empty()
+}
@@ -0,0 +1,97 @@
+//! Optional ORCA-based local collision avoidance between ships, using `dodgy`.
+//! When enabled it replaces `apply_acceleration`: the velocity each ship wants after steering is
+//! fed to dodgy as the preferred velocity, and the collision-free result is written back.
+//! Avoidance only happens in the xz plane; in volumetric mode the vertical velocity is left as desired.
+
+use crate::*;
+use dodgy::{Agent, AvoidanceOptions, Vec2};
+
+#[derive(Resource)]
+pub struct Avoidance {
+    pub enabled: bool,
+    /// How many seconds ahead collisions between ships are considered.
+    pub time_horizon: f32,
+}
+
+impl Default for Avoidance {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time_horizon: 2.0,
+        }
+    }
+}
+
+pub fn avoidance_enabled(avoidance: Res<Avoidance>) -> bool {
+    avoidance.enabled
+}
+
+pub fn avoidance_disabled(avoidance: Res<Avoidance>) -> bool {
+    !avoidance.enabled
+}
+
+pub fn toggle_avoidance(mut avoidance: ResMut<Avoidance>) {
+    avoidance.enabled = !avoidance.enabled;
+    info!("collision avoidance {}", if avoidance.enabled { "enabled" } else { "disabled" });
+}
+
+fn to_agent(position: Vec3, vel: &Velocity, radius: &Radius) -> Agent {
+    Agent {
+        position: Vec2::new(position.x, position.z),
+        velocity: Vec2::new(vel.velocity.x, vel.velocity.z),
+        radius: radius.radius,
+        max_velocity: vel.max_velocity,
+        avoidance_responsibility: 1.0,
+    }
+}
+
+pub fn avoid_collisions(
+    time: Res<Time>,
+    mode: Res<FlightMode>,
+    avoidance: Res<Avoidance>,
+    params: Res<FlockingParams>,
+    index: Res<SpatialIndex<Ship>>,
+    mut query: Query<(Entity, &Transform, &mut Velocity, &Acceleration, &Radius)>,
+) {
+    let dt = time.delta_seconds();
+    if dt <= 0.0 {
+        return;
+    }
+    let options = AvoidanceOptions {
+        obstacle_margin: 0.0,
+        time_horizon: avoidance.time_horizon,
+        obstacle_time_horizon: avoidance.time_horizon,
+    };
+
+    let mut new_velocities = Vec::new();
+    let mut neighbours = Vec::new();
+    for (entity1, trans1, vel1, acc1, radius1) in &query {
+        let agent = to_agent(trans1.translation, vel1, radius1);
+        let preferred = desired_velocity(vel1, acc1, dt, *mode);
+
+        neighbours.clear();
+        index.query_within(trans1.translation, params.query_radius(), |entity2, pos2, _| {
+            if entity1 != entity2 {
+                if let Ok((_, _, vel2, _, radius2)) = query.get(entity2) {
+                    neighbours.push(to_agent(pos2, vel2, radius2));
+                }
+            }
+        });
+
+        let neighbour_refs: Vec<&Agent> = neighbours.iter().collect();
+        let avoiding = agent.compute_avoiding_velocity(
+            &neighbour_refs,
+            &[],
+            Vec2::new(preferred.x, preferred.z),
+            dt,
+            &options,
+        );
+        new_velocities.push((entity1, Vec3::new(avoiding.x, preferred.y, avoiding.y)));
+    }
+
+    for (entity, velocity) in new_velocities {
+        if let Ok((_, _, mut vel, _, _)) = query.get_mut(entity) {
+            vel.velocity = velocity;
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Reports the time per frame of the headless flocking simulation for 1k, 10k and 50k ships, with
+//! each spatial index backend. Takes the game's `--scenario`, `--seed`, `--tick-rate`, `--3d` and
+//! `--avoidance` options. Run with `cargo run --release --bin bench`.
+
+fn main() {
+    spacerust::headless::bench(&spacerust::Options::bench_from_args());
+}
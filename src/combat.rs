@@ -0,0 +1,192 @@
+//! Ships shooting at the nearest enemy in range, projectiles, damage and destruction.
+
+use crate::*;
+
+/// Cell size of the projectiles' spatial index. Projectiles are small and many, so cells are small too.
+const PROJECTILE_CELL_SIZE: f32 = 10.0;
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        // shots leave the plane when fired at ships above or below, so this index is always volumetric
+        app.add_plugins(SpatialIndexPlugin::<Projectile>::new(PROJECTILE_CELL_SIZE, true))
+            .add_systems(
+                FixedUpdate,
+                // combat finds ships through their index, so it runs once the index has caught up
+                // with where they moved this tick
+                (acquire_targets, fire_weapons, move_projectiles, destroy_ships)
+                    .chain()
+                    .after(SpatialIndexSet),
+            );
+    }
+}
+
+#[derive(Component)]
+pub struct Health {
+    pub hit_points: f32,
+}
+
+pub struct Weapon {
+    pub stats: WeaponStats,
+    /// Seconds until the weapon can fire again.
+    pub reload_timer: f32,
+    pub target: Option<Entity>,
+}
+
+impl Weapon {
+    pub fn new(stats: &WeaponStats) -> Self {
+        Self {
+            stats: *stats,
+            reload_timer: 0.0,
+            target: None,
+        }
+    }
+}
+
+/// The weapons of a ship. Each picks its own target, within its own range.
+#[derive(Component)]
+pub struct Armament {
+    pub weapons: Vec<Weapon>,
+}
+
+impl Armament {
+    pub fn new(weapons: &[WeaponStats]) -> Self {
+        Self {
+            weapons: weapons.iter().map(Weapon::new).collect(),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Projectile {
+    velocity: Vec3,
+    damage: f32,
+    /// Projectiles don't hit ships of the faction that fired them.
+    faction: Faction,
+    /// Seconds until the projectile disappears without hitting anything.
+    lifetime: f32,
+}
+
+fn acquire_targets(
+    index: Res<SpatialIndex<Ship>>,
+    mut query: Query<(&Transform, &Faction, &mut Armament)>,
+    targets: Query<(&Transform, &Faction), With<Health>>,
+) {
+    for (trans1, faction1, mut armament) in &mut query {
+        for weapon in &mut armament.weapons {
+            let in_range = |target: Entity| {
+                targets.get(target).is_ok_and(|(trans2, faction2)| {
+                    faction2 != faction1 && trans1.translation.distance(trans2.translation) <= weapon.stats.range
+                })
+            };
+            if weapon.target.is_some_and(in_range) {
+                continue;
+            }
+
+            let nearest = index.query_k_nearest(trans1.translation, 1, weapon.stats.range, |entity2| {
+                targets.get(entity2).is_ok_and(|(_, faction2)| faction2 != faction1)
+            });
+            weapon.target = nearest.first().map(|(entity, _)| *entity);
+        }
+    }
+}
+
+fn fire_weapons(
+    mut commands: Commands,
+    time: Res<Time>,
+    index: Res<SpatialIndex<Ship>>,
+    mut query: Query<(Entity, &Transform, &Faction, &mut Armament)>,
+    targets: Query<(&Transform, &Velocity)>,
+    ships: Query<(&Faction, &Radius)>,
+) {
+    for (entity, transform, faction, mut armament) in &mut query {
+        for weapon in &mut armament.weapons {
+            weapon.reload_timer = (weapon.reload_timer - time.delta_seconds()).max(0.0);
+            if weapon.reload_timer > 0.0 {
+                continue;
+            }
+            let Some(Ok((target_transform, target_vel))) = weapon.target.map(|target| targets.get(target)) else {
+                continue;
+            };
+
+            // lead the target by where it will be when the projectile gets there
+            let dist = transform.translation.distance(target_transform.translation);
+            let predicted = target_transform.translation + target_vel.velocity * (dist / weapon.stats.projectile_speed);
+            let Some(direction) = (predicted - transform.translation).try_normalize() else {
+                continue;
+            };
+
+            // hold fire while a friendly ship is in the line of fire
+            let first_hit = index.raycast(transform.translation, direction, dist, |other| {
+                if other == entity {
+                    return None;
+                }
+                ships.get(other).ok().map(|(_, radius)| radius.radius)
+            });
+            if first_hit.is_some_and(|(other, _)| ships.get(other).is_ok_and(|(f, _)| f == faction)) {
+                continue;
+            }
+
+            weapon.reload_timer = weapon.stats.cooldown;
+            let projectile_transform = Transform::from_translation(transform.translation);
+            commands.spawn((
+                Projectile {
+                    velocity: direction * weapon.stats.projectile_speed,
+                    damage: weapon.stats.damage,
+                    faction: *faction,
+                    lifetime: weapon.stats.range / weapon.stats.projectile_speed * 1.5,
+                },
+                CellAssociation::<Projectile>::new(),
+                SimulatedTransform::new(projectile_transform),
+                TransformBundle::from_transform(projectile_transform),
+            ));
+        }
+    }
+}
+
+fn move_projectiles(
+    mut commands: Commands,
+    time: Res<Time>,
+    index: Res<SpatialIndex<Ship>>,
+    mut projectiles: Query<(Entity, &mut Transform, &mut Projectile)>,
+    mut ships: Query<(&Faction, &Radius, &mut Health)>,
+) {
+    for (entity, mut transform, mut projectile) in &mut projectiles {
+        // test the whole segment travelled this frame, so fast projectiles can't pass through ships
+        let step = projectile.velocity * time.delta_seconds();
+        let hit = index.raycast(transform.translation, step, step.length(), |ship| match ships.get(ship) {
+            Ok((faction, radius, _)) if *faction != projectile.faction => Some(radius.radius),
+            _ => None,
+        });
+        transform.translation += step;
+        projectile.lifetime -= time.delta_seconds();
+
+        if let Some((ship, _)) = hit {
+            if let Ok((_, _, mut health)) = ships.get_mut(ship) {
+                health.hit_points -= projectile.damage;
+            }
+            commands.entity(entity).despawn();
+        } else if projectile.lifetime <= 0.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn destroy_ships(
+    mut commands: Commands,
+    query: Query<(Entity, &Health)>,
+) {
+    for (entity, health) in &query {
+        if health.hit_points <= 0.0 {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub fn draw_projectiles(query: Query<(&Transform, &Projectile)>, mut gizmos: Gizmos) {
+    for (transform, projectile) in &query {
+        let tail = transform.translation - projectile.velocity.normalize_or_zero();
+        gizmos.line(tail, transform.translation, projectile.faction.color());
+    }
+}
@@ -0,0 +1,84 @@
+//! Tunable flocking parameters, loaded from a RON asset and hot-reloaded when it changes.
+
+use crate::ron_asset::*;
+use bevy::{asset::AssetEvent, prelude::*};
+use serde::Deserialize;
+
+pub const DEFAULT_FLOCKING_PARAMS: &str = "default.flocking.ron";
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct BehaviourParams {
+    pub weight: f32,
+    /// Only neighbours closer than this contribute to the behaviour.
+    pub radius: f32,
+}
+
+#[derive(Asset, Resource, TypePath, Clone, Deserialize)]
+pub struct FlockingParams {
+    pub separation: BehaviourParams,
+    pub alignment: BehaviourParams,
+    pub cohesion: BehaviourParams,
+    /// Weight of steering towards the destination of a move order.
+    pub order_weight: f32,
+    /// Distance from the target at which `Boid::arrive` starts slowing down.
+    pub arrive_brake_distance: f32,
+    /// When non-zero, ships only react to this many of their nearest neighbours (topological
+    /// flocking), instead of to every neighbour in range.
+    #[serde(default)]
+    pub topological_neighbours: usize,
+}
+
+impl Default for FlockingParams {
+    fn default() -> Self {
+        Self {
+            separation: BehaviourParams { weight: 1.0, radius: 10.0 },
+            alignment: BehaviourParams { weight: 1.0, radius: 20.0 },
+            cohesion: BehaviourParams { weight: 1.0, radius: 20.0 },
+            order_weight: 1.0,
+            arrive_brake_distance: 50.0,
+            topological_neighbours: 0,
+        }
+    }
+}
+
+impl FlockingParams {
+    /// Radius which covers every neighbour that any behaviour is interested in.
+    pub fn query_radius(&self) -> f32 {
+        self.separation.radius.max(self.alignment.radius).max(self.cohesion.radius)
+    }
+}
+
+#[derive(Resource)]
+struct FlockingParamsHandle(Handle<FlockingParams>);
+
+/// Loads `FlockingParams` through the `AssetServer` and keeps the resource in sync with the file.
+pub struct FlockingParamsPlugin;
+
+impl Plugin for FlockingParamsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<FlockingParams>()
+            .register_asset_loader(RonAssetLoader::<FlockingParams>::new(&["flocking.ron"]))
+            .add_systems(Startup, load_flocking_params)
+            .add_systems(Update, apply_flocking_params);
+    }
+}
+
+fn load_flocking_params(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(FlockingParamsHandle(asset_server.load(DEFAULT_FLOCKING_PARAMS)));
+}
+
+fn apply_flocking_params(
+    mut events: EventReader<AssetEvent<FlockingParams>>,
+    handle: Res<FlockingParamsHandle>,
+    assets: Res<Assets<FlockingParams>>,
+    mut params: ResMut<FlockingParams>,
+) {
+    for event in events.read() {
+        if event.is_loaded_with_dependencies(&handle.0) || event.is_modified(&handle.0) {
+            if let Some(loaded) = assets.get(&handle.0) {
+                *params = loaded.clone();
+                info!("applied flocking parameters from {}", DEFAULT_FLOCKING_PARAMS);
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Formation templates for moving groups of ships, and assignment of ships to formation slots.
+
+use crate::*;
+use std::f32::consts::TAU;
+
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Formation {
+    #[default]
+    Line,
+    Wedge,
+    Box,
+    Circle,
+}
+
+impl Formation {
+    /// Slot offsets for `count` ships, `spacing` apart. x is to the right of the group, y is forward.
+    fn slots(self, count: usize, spacing: f32) -> Vec<Vec2> {
+        match self {
+            Formation::Line => (0..count)
+                .map(|i| Vec2::new((i as f32 - (count - 1) as f32 * 0.5) * spacing, 0.0))
+                .collect(),
+            Formation::Wedge => (0..count)
+                .map(|i| {
+                    // the leader is at the tip, followed by pairs on alternating sides
+                    let row = i.div_ceil(2) as f32;
+                    let side = if i % 2 == 0 { 1.0 } else { -1.0 };
+                    Vec2::new(side * row * spacing, -row * spacing)
+                })
+                .collect(),
+            Formation::Box => {
+                let columns = (count as f32).sqrt().ceil() as usize;
+                let rows = count.div_ceil(columns.max(1));
+                (0..count)
+                    .map(|i| {
+                        let column = (i % columns) as f32 - (columns - 1) as f32 * 0.5;
+                        let row = (i / columns) as f32 - (rows - 1) as f32 * 0.5;
+                        Vec2::new(column * spacing, -row * spacing)
+                    })
+                    .collect()
+            }
+            Formation::Circle => {
+                if count == 1 {
+                    return vec![Vec2::ZERO];
+                }
+                let radius = (spacing * count as f32 / TAU).max(spacing);
+                (0..count)
+                    .map(|i| Vec2::from_angle(TAU * i as f32 / count as f32) * radius)
+                    .collect()
+            }
+        }
+    }
+}
+
+pub fn select_formation(key_input: Res<ButtonInput<KeyCode>>, mut formation: ResMut<Formation>) {
+    let selected = if key_input.just_pressed(KeyCode::Digit1) {
+        Formation::Line
+    } else if key_input.just_pressed(KeyCode::Digit2) {
+        Formation::Wedge
+    } else if key_input.just_pressed(KeyCode::Digit3) {
+        Formation::Box
+    } else if key_input.just_pressed(KeyCode::Digit4) {
+        Formation::Circle
+    } else {
+        return;
+    };
+    *formation = selected;
+    info!("formation: {:?}", selected);
+}
+
+/// Places the slots of `formation` around `target`, facing away from the group's current centre,
+/// and returns the slot position assigned to each of `positions`, minimizing total travel distance.
+pub fn formation_targets(formation: Formation, positions: &[Vec3], target: Vec3, spacing: f32) -> Vec<Vec3> {
+    if positions.is_empty() {
+        return Vec::new();
+    }
+    let centroid = positions.iter().sum::<Vec3>() / positions.len() as f32;
+    let mut forward = target - centroid;
+    forward.y = 0.0;
+    let forward = forward.try_normalize().unwrap_or(Vec3::Z);
+    let right = forward.cross(Vec3::Y);
+
+    let slots: Vec<Vec3> = formation
+        .slots(positions.len(), spacing)
+        .into_iter()
+        .map(|offset| target + right * offset.x + forward * offset.y)
+        .collect();
+
+    let costs: Vec<Vec<f32>> = positions
+        .iter()
+        .map(|pos| slots.iter().map(|slot| pos.distance(*slot)).collect())
+        .collect();
+    assign(&costs).into_iter().map(|slot| slots[slot]).collect()
+}
+
+/// Solves the assignment problem for a square cost matrix with the Hungarian algorithm,
+/// returning the column assigned to each row.
+fn assign(costs: &[Vec<f32>]) -> Vec<usize> {
+    let n = costs.len();
+    // potentials and matching are 1-based, with index 0 used as a sentinel
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; n + 1];
+    let mut row_of_column = vec![0; n + 1];
+    let mut way = vec![0; n + 1];
+
+    for row in 1..=n {
+        row_of_column[0] = row;
+        let mut column = 0;
+        let mut min_slack = vec![f32::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[column] = true;
+            let current_row = row_of_column[column];
+            let mut delta = f32::INFINITY;
+            let mut next_column = 0;
+            for c in 1..=n {
+                if used[c] {
+                    continue;
+                }
+                let slack = costs[current_row - 1][c - 1] - u[current_row] - v[c];
+                if slack < min_slack[c] {
+                    min_slack[c] = slack;
+                    way[c] = column;
+                }
+                if min_slack[c] < delta {
+                    delta = min_slack[c];
+                    next_column = c;
+                }
+            }
+            for c in 0..=n {
+                if used[c] {
+                    u[row_of_column[c]] += delta;
+                    v[c] -= delta;
+                } else {
+                    min_slack[c] -= delta;
+                }
+            }
+            column = next_column;
+            if row_of_column[column] == 0 {
+                break;
+            }
+        }
+        loop {
+            let previous = way[column];
+            row_of_column[column] = row_of_column[previous];
+            column = previous;
+            if column == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut column_of_row = vec![0; n];
+    for c in 1..=n {
+        column_of_row[row_of_column[c] - 1] = c - 1;
+    }
+    column_of_row
+}
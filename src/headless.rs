@@ -0,0 +1,297 @@
+//! Runs the flocking simulation without a window or GPU, for CI and batch experiments.
+
+use crate::*;
+use crate::ron_asset::load_ron_file;
+use bevy::{log::LogPlugin, time::TimeUpdateStrategy};
+use std::time::Instant;
+
+/// Numbers of ships the `bench` binary measures, and how many frames each is simulated for before
+/// and while being timed.
+const BENCH_SHIP_COUNTS: [usize; 3] = [1_000, 10_000, 50_000];
+const BENCH_WARMUP_TICKS: u32 = 10;
+const BENCH_TICKS: u32 = 100;
+
+pub fn run(options: &Options) {
+    let scenario = load_scenario(&options.scenario);
+    let seed = resolve_seed(options, &scenario);
+    let mut app = App::new();
+    app.add_plugins(LogPlugin::default());
+    simulate(&mut app, options, &scenario, seed);
+    print_summary(&mut app.world, options.ticks);
+}
+
+/// Simulates `options.ticks` ticks twice from the same start, and reports whether every ship
+/// ended up at bit for bit the same position both times. Fails when no ships are left to compare.
+pub fn verify_determinism(options: &Options) -> bool {
+    let scenario = load_scenario(&options.scenario);
+    let seed = resolve_seed(options, &scenario);
+    let mut first = App::new();
+    first.add_plugins(LogPlugin::default());
+    let first = final_ship_positions(first, options, &scenario, seed);
+    let second = final_ship_positions(App::new(), options, &scenario, seed);
+    if let Some(difference) = first_difference(&first, &second) {
+        println!("not deterministic: {}", difference);
+        return false;
+    }
+    if first.is_empty() {
+        println!("inconclusive: no ships are left, so there is nothing to compare");
+        return false;
+    }
+    println!("deterministic: {} ships at identical positions after {} ticks", first.len(), options.ticks);
+    true
+}
+
+/// Simulates `scenario` in `app`, and returns where its ships ended up.
+fn final_ship_positions(mut app: App, options: &Options, scenario: &Scenario, seed: u64) -> Vec<(Entity, Vec3)> {
+    simulate(&mut app, options, scenario, seed);
+    ship_positions(&mut app.world)
+}
+
+/// Describes the first way the ships at the end of two runs differ, if they aren't bit for bit the same.
+fn first_difference(first: &[(Entity, Vec3)], second: &[(Entity, Vec3)]) -> Option<String> {
+    if first.len() != second.len() {
+        return Some(format!("{} ships in the first run, {} in the second", first.len(), second.len()));
+    }
+    let bits = |pos: Vec3| pos.to_array().map(f32::to_bits);
+    first.iter().zip(second).find(|((_, a), (_, b))| bits(*a) != bits(*b)).map(|((entity, a), (_, b))| {
+        format!("ship {:?} ended at {} in the first run, {} in the second", entity, a, b)
+    })
+}
+
+/// Adds the simulation of `scenario` to `app`, and runs it for `options.ticks` ticks.
+fn simulate(app: &mut App, options: &Options, scenario: &Scenario, seed: u64) {
+    add_simulation(app, options, scenario, seed);
+    start_ticking(app);
+    for _ in 0..options.ticks {
+        app.update();
+    }
+}
+
+fn add_simulation(app: &mut App, options: &Options, scenario: &Scenario, seed: u64) {
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(FlockingPlugin { seed, ..options.flocking_plugin() })
+        .add_plugins(CombatPlugin)
+        .insert_resource(load_flocking_params())
+        .insert_resource(scenario.clone())
+        .insert_resource(load_ship_classes(scenario))
+        .add_systems(Startup, setup_headless);
+}
+
+/// Finishes building `app` and runs its first update, which spawns the ships but only starts the
+/// clock. From then on, time is stepped manually by exactly one fixed tick per update, so results
+/// don't depend on how fast the host machine is.
+fn start_ticking(app: &mut App) {
+    let timestep = app.world.resource::<Time<Fixed>>().timestep();
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(timestep));
+    app.finish();
+    app.cleanup();
+    app.update();
+}
+
+/// The positions of every ship, ordered by entity.
+fn ship_positions(world: &mut World) -> Vec<(Entity, Vec3)> {
+    let mut query = world.query_filtered::<(Entity, &Transform), With<Ship>>();
+    let mut positions: Vec<(Entity, Vec3)> = query.iter(world).map(|(entity, transform)| (entity, transform.translation)).collect();
+    positions.sort_by_key(|(entity, _)| *entity);
+    positions
+}
+
+/// Reports the time per frame of the flocking simulation for increasing numbers of ships, with
+/// each spatial index backend.
+pub fn bench(options: &Options) {
+    if cfg!(debug_assertions) {
+        println!("debug assertions are enabled, so timings include the spatial index check; run with --release to leave it out");
+    }
+    let params = load_flocking_params();
+    let scenario = load_scenario(&options.scenario);
+    let seed = resolve_seed(options, &scenario);
+    let classes = load_ship_classes(&scenario);
+    for count in BENCH_SHIP_COUNTS {
+        let scenario = scale_scenario(&scenario, count);
+        // the grid covers about twice the area the ships start out in
+        let half_extents = scenario.ship_groups.iter().fold(Vec3::ZERO, |extents, group| {
+            extents.max(group.region.min.abs()).max(group.region.max.abs())
+        }) * 2.0;
+        let backends = [
+            ("hash map", SpatialIndexBackend::HashMap),
+            ("dense grid", SpatialIndexBackend::DenseGrid { min: -half_extents, max: half_extents }),
+        ];
+        for (name, index_backend) in backends {
+            let mut app = App::new();
+            app.add_plugins(MinimalPlugins)
+                .add_plugins(FlockingPlugin { seed, index_backend, ..options.flocking_plugin() })
+                .insert_resource(params.clone())
+                .insert_resource(scenario.clone())
+                .insert_resource(classes.clone())
+                .add_systems(Startup, setup_headless);
+            start_ticking(&mut app);
+            for _ in 0..BENCH_WARMUP_TICKS {
+                app.update();
+            }
+            let start = Instant::now();
+            for _ in 0..BENCH_TICKS {
+                app.update();
+            }
+            let frame_ms = start.elapsed().as_secs_f64() * 1000.0 / BENCH_TICKS as f64;
+            println!("{:>6} ships, {:<10}: {:8.2} ms/frame", scenario.ship_count(), name, frame_ms);
+        }
+    }
+}
+
+/// There is no `AssetServer` here, so the parameters are read once from disk instead.
+fn load_flocking_params() -> FlockingParams {
+    match load_ron_file(DEFAULT_FLOCKING_PARAMS) {
+        Ok(params) => params,
+        Err(err) => {
+            warn!("using default flocking parameters, {} failed to load: {}", DEFAULT_FLOCKING_PARAMS, err);
+            FlockingParams::default()
+        }
+    }
+}
+
+/// The scenario is read from disk up front too. Headless runs can't do without one.
+fn load_scenario(path: &str) -> Scenario {
+    load_ron_file(path).unwrap_or_else(|err| {
+        eprintln!("scenario {} failed to load: {}", path, err);
+        std::process::exit(1);
+    })
+}
+
+/// Reads the ship classes the scenario uses from disk.
+fn load_ship_classes(scenario: &Scenario) -> ShipClasses {
+    let mut classes = ShipClasses::default();
+    for name in scenario.class_names() {
+        match load_ron_file(&ShipClass::path(name)) {
+            Ok(class) => classes.insert(name.to_string(), class),
+            Err(err) => {
+                eprintln!("ship class {} failed to load: {}", name, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    classes
+}
+
+/// The seed from the command line, or else the scenario's, or else a random one.
+fn resolve_seed(options: &Options, scenario: &Scenario) -> u64 {
+    options.seed.or(scenario.seed).unwrap_or_else(rand::random)
+}
+
+/// Scales `scenario` to about `count` ships, growing their regions so they start out as densely packed.
+fn scale_scenario(scenario: &Scenario, count: usize) -> Scenario {
+    let factor = count as f32 / scenario.ship_count().max(1) as f32;
+    let mut scaled = scenario.clone();
+    for group in &mut scaled.ship_groups {
+        group.count = (group.count as f32 * factor).round() as usize;
+        group.region.min *= factor.sqrt();
+        group.region.max *= factor.sqrt();
+    }
+    scaled
+}
+
+fn setup_headless(
+    mut commands: Commands,
+    scenario: Res<Scenario>,
+    classes: Res<ShipClasses>,
+    mode: Res<FlightMode>,
+    mut rng: ResMut<SimRng>,
+) {
+    rng.log_seed();
+    spawn_ship_bodies(&mut commands, &scenario, &classes, &mut rng, *mode);
+}
+
+fn print_summary(world: &mut World, ticks: u32) {
+    let mut query = world.query::<(&Transform, &Velocity)>();
+    let ships: Vec<(Vec3, f32)> = query
+        .iter(world)
+        .map(|(transform, vel)| (transform.translation, vel.velocity.length()))
+        .collect();
+    let count = ships.len().max(1) as f32;
+    let centroid = ships.iter().map(|(pos, _)| *pos).sum::<Vec3>() / count;
+    let spread = ships.iter().map(|(pos, _)| (*pos - centroid).length()).fold(0.0, f32::max);
+    let mean_speed = ships.iter().map(|(_, speed)| *speed).sum::<f32>() / count;
+
+    let mut closest = f32::INFINITY;
+    for (i, (a, _)) in ships.iter().enumerate() {
+        for (b, _) in &ships[i + 1..] {
+            closest = closest.min((*a - *b).length());
+        }
+    }
+
+    println!("ticks:           {}", ticks);
+    let timestep = world.resource::<Time<Fixed>>().timestep();
+    println!("simulated time:  {:.2}s", (timestep * ticks).as_secs_f32());
+    println!("ships:           {}", ships.len());
+    println!("centroid:        {:.2} {:.2} {:.2}", centroid.x, centroid.y, centroid.z);
+    println!("spread:          {:.2}", spread);
+    println!("mean speed:      {:.2}", mean_speed);
+    println!("closest pair:    {:.2}", closest);
+    let stats = world.resource::<SpatialIndex<Ship>>().stats();
+    println!("occupied cells:  {}", stats.cell_count);
+    println!("cell occupancy:  {:.2} mean, {} max", stats.mean_occupancy, stats.max_occupancy);
+
+    let mut factions = world.query::<&Faction>();
+    let mut survivors = std::collections::BTreeMap::new();
+    for faction in factions.iter(world) {
+        *survivors.entry(faction.id).or_insert(0) += 1;
+    }
+    for (faction, count) in survivors {
+        println!("faction {}:       {} ships", faction, count);
+    }
+    let mut projectiles = world.query::<&Projectile>();
+    println!("projectiles:     {}", projectiles.iter(world).count());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::schedule::{LogLevel, ScheduleBuildSettings, ScheduleLabel};
+
+    const SEED: u64 = 7;
+
+    fn assert_deterministic(options: Options) {
+        let scenario = load_scenario(&options.scenario);
+        let first = final_ship_positions(App::new(), &options, &scenario, SEED);
+        let second = final_ship_positions(App::new(), &options, &scenario, SEED);
+        assert!(!first.is_empty(), "every ship was destroyed, so there is nothing to compare");
+        if let Some(difference) = first_difference(&first, &second) {
+            panic!("not deterministic: {}", difference);
+        }
+    }
+
+    #[test]
+    fn planar_runs_with_the_same_seed_match() {
+        assert_deterministic(Options::default());
+    }
+
+    #[test]
+    fn volumetric_runs_with_avoidance_and_the_same_seed_match() {
+        assert_deterministic(Options {
+            volumetric: true,
+            avoidance: true,
+            ..Options::default()
+        });
+    }
+
+    /// Systems with conflicting data access and no order between them run in whichever order the
+    /// executor gets to them first, which is how nondeterminism got into the simulation before.
+    #[test]
+    fn fixed_schedules_have_no_ambiguous_systems() {
+        let options = Options::default();
+        let scenario = load_scenario(&options.scenario);
+        let mut app = App::new();
+        add_simulation(&mut app, &options, &scenario, SEED);
+        let labels = [FixedFirst.intern(), FixedPreUpdate.intern(), FixedUpdate.intern(), FixedPostUpdate.intern(), FixedLast.intern()];
+        for label in labels {
+            let _ = app.world.try_schedule_scope(label, |world, schedule| {
+                schedule.set_build_settings(ScheduleBuildSettings {
+                    ambiguity_detection: LogLevel::Error,
+                    ..default()
+                });
+                if let Err(err) = schedule.initialize(world) {
+                    panic!("{:?}: {}", label, err);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Smooths the motion of entities simulated in `FixedUpdate`, by rendering them part of the way
+//! between their last two simulated states.
+
+use bevy::{prelude::*, transform::TransformSystem};
+
+/// The last two simulated states of an entity. Between fixed ticks its `Transform` is interpolated
+/// between them, and it is put back to the latest one before the next tick, so what is rendered
+/// never feeds back into the simulation.
+#[derive(Component, Clone, Copy)]
+pub struct SimulatedTransform {
+    previous: Transform,
+    current: Transform,
+}
+
+impl SimulatedTransform {
+    pub fn new(transform: Transform) -> Self {
+        Self {
+            previous: transform,
+            current: transform,
+        }
+    }
+}
+
+pub struct InterpolationPlugin;
+
+impl Plugin for InterpolationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedFirst, restore_simulated_transforms)
+            .add_systems(FixedLast, record_simulated_transforms)
+            .add_systems(PostUpdate, interpolate_transforms.before(TransformSystem::TransformPropagate));
+    }
+}
+
+fn restore_simulated_transforms(mut query: Query<(&mut Transform, &SimulatedTransform)>) {
+    for (mut transform, simulated) in &mut query {
+        transform.set_if_neq(simulated.current);
+    }
+}
+
+fn record_simulated_transforms(mut query: Query<(&Transform, &mut SimulatedTransform)>) {
+    for (transform, mut simulated) in &mut query {
+        simulated.previous = simulated.current;
+        simulated.current = *transform;
+    }
+}
+
+/// Places entities between their last two simulated states, by how far time has got towards the next tick.
+fn interpolate_transforms(time: Res<Time<Fixed>>, mut query: Query<(&mut Transform, &SimulatedTransform)>) {
+    let t = time.overstep_fraction();
+    for (mut transform, simulated) in &mut query {
+        let (previous, current) = (simulated.previous, simulated.current);
+        transform.set_if_neq(Transform {
+            translation: previous.translation.lerp(current.translation, t),
+            rotation: previous.rotation.slerp(current.rotation, t),
+            scale: previous.scale.lerp(current.scale, t),
+        });
+    }
+}
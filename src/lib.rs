@@ -0,0 +1,898 @@
+//! A space fleet game of flocking ships built on Bevy, along with headless runs of its simulation.
+
+#[path = "./camera_controller.rs"]
+mod camera_controller;
+
+#[path = "./spatial_index.rs"]
+mod spatial_index;
+
+#[path = "./headless.rs"]
+pub mod headless;
+
+#[path = "./avoidance.rs"]
+mod avoidance;
+
+#[path = "./ron_asset.rs"]
+mod ron_asset;
+
+#[path = "./flocking_params.rs"]
+mod flocking_params;
+
+#[path = "./selection.rs"]
+mod selection;
+
+#[path = "./orders.rs"]
+mod orders;
+
+#[path = "./formation.rs"]
+mod formation;
+
+#[path = "./combat.rs"]
+mod combat;
+
+#[path = "./interpolation.rs"]
+mod interpolation;
+
+#[path = "./sim_rng.rs"]
+mod sim_rng;
+
+#[path = "./scenario.rs"]
+mod scenario;
+
+#[path = "./ship_class.rs"]
+mod ship_class;
+
+#[path = "./asset_fallback.rs"]
+mod asset_fallback;
+
+use bevy::{
+    asset::LoadState, core_pipeline::Skybox, ecs::system::EntityCommands, input::common_conditions::input_just_pressed, math::Vec3A, prelude::*, render::{
+        primitives::Aabb, render_resource::{TextureViewDescriptor, TextureViewDimension}
+    }, window::{close_on_esc, PrimaryWindow, WindowMode}
+};
+use asset_fallback::*;
+use avoidance::*;
+use camera_controller::{CameraController, CameraControllerPlugin};
+use combat::*;
+use flocking_params::*;
+use interpolation::{InterpolationPlugin, SimulatedTransform};
+use orders::*;
+use scenario::*;
+use ship_class::*;
+use selection::SelectionPlugin;
+use sim_rng::*;
+use spatial_index::*;
+use std::{f32::consts::PI, time::Duration};
+use rand::prelude::*;
+
+
+#[derive(Resource)]
+struct SkyboxResource {
+    is_loaded: bool,
+    image_handle: Handle<Image>,
+}
+
+#[derive(Resource)]
+struct CursorPosition {
+    position: Vec3,
+}
+
+#[derive(Component)]
+struct Ship;
+
+#[derive(Component)]
+struct UnadjustedAABB;
+
+#[derive(Component)]
+struct UnadjustedMaterial;
+
+/// Marks ships whose materials have not been tinted with their faction colour yet.
+#[derive(Component)]
+struct UntintedFaction;
+
+/// The side a ship fights for. Ships only align and cohere with their own faction.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+struct Faction {
+    id: u8,
+}
+
+impl Faction {
+    fn color(self) -> Color {
+        const COLORS: [Color; 4] = [
+            Color::rgb(0.4, 0.6, 1.0),
+            Color::rgb(1.0, 0.4, 0.3),
+            Color::rgb(0.4, 1.0, 0.4),
+            Color::rgb(1.0, 0.9, 0.3),
+        ];
+        COLORS[self.id as usize % COLORS.len()]
+    }
+}
+
+#[derive(Component)]
+struct Velocity {
+    velocity: Vec3,
+    max_velocity: f32,
+    max_force: f32,
+    turn_speed: f32,
+}
+
+#[derive(Component)]
+struct Acceleration {
+    acceleration: Vec3,
+}
+
+/// Bounding radius of a ship, used for collision avoidance, hits and picking.
+#[derive(Component)]
+struct Radius {
+    radius: f32,
+}
+
+/// Whether ships flock in a volume, or are kept in the y=0 plane.
+#[derive(Resource, Clone, Copy)]
+struct FlightMode {
+    volumetric: bool,
+}
+
+/// Largest roll angle ships bank to when turning in volumetric mode.
+const MAX_BANK_ANGLE: f32 = PI * 0.25;
+
+/// Cell size of the ships' spatial index. Should be about the largest flocking radius.
+const SHIP_CELL_SIZE: f32 = 20.0;
+
+/// Extent of the world covered by the ships' spatial index with `--dense-grid`, around the origin.
+const DENSE_GRID_HALF_EXTENTS: Vec3 = Vec3::new(1000.0, 250.0, 1000.0);
+
+/// Simulation ticks per second, unless given with `--tick-rate`.
+const DEFAULT_TICK_RATE: f64 = 60.0;
+
+
+
+fn limit(v: Vec3, len: f32) -> Vec3 {
+    if v.length() > len {
+        return v.normalize() * len;
+    }
+    v
+}
+
+struct Boid<'a> {
+    params: &'a FlockingParams,
+    transform: &'a Transform,
+    velocity: &'a Velocity,
+    faction: Faction,
+
+    sep_sum: Vec3,
+    sep_count: i32,
+
+    align_sum: Vec3,
+    align_count: i32,
+
+    cohesion_sum: Vec3,
+    cohesion_count: i32,
+}
+
+impl<'a> Boid<'a> {
+    fn new(params: &'a FlockingParams, transform: &'a Transform, velocity: &'a Velocity, faction: Faction) -> Self {
+        Self {
+            params,
+            transform,
+            velocity,
+            faction,
+            sep_sum: Vec3::ZERO,
+            sep_count: 0,
+            align_sum: Vec3::ZERO,
+            align_count: 0,
+            cohesion_sum: Vec3::ZERO,
+            cohesion_count: 0,
+        }
+    }
+
+    fn add_other(&mut self, position: Vec3, velocity: &Velocity, faction: Faction) {
+        let delta = self.transform.translation - position;
+        let dist = delta.length();
+        if !(0.001..=self.params.query_radius()).contains(&dist) {
+            return
+        }
+
+        if dist < self.params.separation.radius {
+            self.sep_sum += delta.normalize() / dist;
+            self.sep_count += 1;
+        }
+
+        if faction != self.faction {
+            return
+        }
+
+        if dist < self.params.alignment.radius {
+            self.align_sum += velocity.velocity;
+            self.align_count += 1;
+        }
+
+        if dist < self.params.cohesion.radius {
+            self.cohesion_sum += position;
+            self.cohesion_count += 1;
+        }
+    }
+
+    fn get_acceleration(&self) -> Vec3 {
+        let mut sum = self.get_separation();
+        if self.align_count > 0 {
+            sum += self.steer(self.align_sum / (self.align_count as f32)) * self.params.alignment.weight;
+        }
+        if self.cohesion_count > 0 {
+            sum += self.seek(self.cohesion_sum / (self.cohesion_count as f32)) * self.params.cohesion.weight;
+        }
+        sum
+    }
+
+    fn get_separation(&self) -> Vec3 {
+        if self.sep_count > 0 {
+            return self.steer(self.sep_sum / (self.sep_count as f32)) * self.params.separation.weight;
+        }
+        Vec3::ZERO
+    }
+
+    fn seek(&self, target: Vec3) -> Vec3 {
+        self.steer(target - self.transform.translation)
+    }
+
+    fn steer(&self, dir: Vec3) -> Vec3 {
+        let len = dir.length();
+        if len < 0.000001 {
+            return Vec3::ZERO;
+        }
+        let adjusted_dir = dir * (self.velocity.max_velocity / len);
+        limit(adjusted_dir - self.velocity.velocity, self.velocity.max_force)
+    }
+
+    fn arrive(&self, target: Vec3) -> Vec3 {
+        let brakelimit = self.params.arrive_brake_distance;
+        let mut desired = target - self.transform.translation;
+        let len = desired.length();
+        if len < 0.000001 {
+            return Vec3::ZERO;
+        }
+        desired /= len;
+        if len < brakelimit {
+            desired *= (len / brakelimit) * self.velocity.max_velocity;
+        } else {
+            desired *= self.velocity.max_velocity;
+        }
+        limit(desired - self.velocity.velocity, self.velocity.max_force)
+    }
+}
+
+
+fn calc_acceleration(
+    params: Res<FlockingParams>,
+    index: Res<SpatialIndex<Ship>>,
+    mut query: Query<(Entity, &Transform, &Velocity, &Faction, &mut Acceleration, &OrderQueue)>,
+    lookup_query: Query<(&Transform, &Velocity, &Faction)>,
+) {
+    // ships only read each other through the index and the lookup query, so they can be done in parallel
+    query.par_iter_mut().for_each(|(entity1, trans1, vel1, faction1, mut acc, orders)| {
+        let mut boid = Boid::new(&params, trans1, vel1, *faction1);
+        if params.topological_neighbours > 0 {
+            let neighbours = index.query_k_nearest(trans1.translation, params.topological_neighbours, params.query_radius(), |entity2| {
+                entity1 != entity2 && lookup_query.contains(entity2)
+            });
+            for (entity2, _) in neighbours {
+                let (trans2, vel2, faction2) = lookup_query.get(entity2).unwrap();
+                boid.add_other(trans2.translation, vel2, *faction2);
+            }
+        } else {
+            index.query_within(trans1.translation, params.query_radius(), |entity2, pos2, _| {
+                if entity1 != entity2 {
+                    if let Ok((_, vel2, faction2)) = lookup_query.get(entity2) {
+                        boid.add_other(pos2, vel2, *faction2);
+                    }
+                }
+            });
+        }
+        // ships under orders keep their distance, but otherwise stop flocking so the
+        // group doesn't drag them past their destination
+        acc.acceleration = match orders.current() {
+            None => boid.get_acceleration(),
+            Some(order @ Order::Patrol { .. }) => {
+                boid.get_separation() + boid.seek(order.target()) * params.order_weight
+            }
+            Some(order) => boid.get_separation() + boid.arrive(order.target()) * params.order_weight,
+        };
+    });
+}
+
+/// The velocity a ship wants to have after applying its acceleration for `dt` seconds.
+fn desired_velocity(vel: &Velocity, acc: &Acceleration, dt: f32, mode: FlightMode) -> Vec3 {
+    let mut velocity = limit(vel.velocity + acc.acceleration * dt, vel.max_velocity);
+    if !mode.volumetric {
+        velocity.y = 0.0;
+    }
+    velocity
+}
+
+fn apply_acceleration(
+    time: Res<Time>,
+    mode: Res<FlightMode>,
+    mut query: Query<(&mut Velocity, &Acceleration)>,
+) {
+    for (mut vel, acc) in &mut query {
+        vel.velocity = desired_velocity(&vel, acc, time.delta_seconds(), *mode);
+    }
+}
+
+fn move_by_velocity(
+    time: Res<Time>,
+    mode: Res<FlightMode>,
+    mut query: Query<(&mut Transform, &Velocity, &Acceleration)>,
+) {
+    for (mut transform, vel, acc) in &mut query {
+        transform.translation += vel.velocity * time.delta_seconds();
+        if !mode.volumetric {
+            transform.translation.y = 0.0;
+        }
+
+        let mut target = transform.looking_to(-vel.velocity, Vec3::Y);
+        if mode.volumetric {
+            // bank into the turn, proportionally to how hard the ship is accelerating sideways
+            let lateral = acc.acceleration.dot(*target.local_x());
+            let bank = (lateral / vel.max_force).clamp(-1.0, 1.0) * MAX_BANK_ANGLE;
+            target.rotate_local_z(-bank);
+        }
+        transform.rotation = transform.rotation.lerp(target.rotation, vel.turn_speed * time.delta_seconds());
+    }
+}
+
+fn draw_axes(mut gizmos: Gizmos) {
+    gizmos.arrow(Vec3::ZERO, Vec3::X * 20.0, Color::RED);
+    gizmos.arrow(Vec3::ZERO, Vec3::Y * 20.0, Color::GREEN);
+    gizmos.arrow(Vec3::ZERO, Vec3::Z * 20.0, Color::BLUE);
+}
+
+fn test_spatial_index(
+    cursor: Res<CursorPosition>,
+    index: Res<SpatialIndex<Ship>>,
+    projectile_index: Res<SpatialIndex<Projectile>>,
+    radii: Query<&Radius>,
+    mut gizmos: Gizmos
+) {
+    let radius = 5.0;
+    let cell_size = index.cell_size();
+    gizmos.circle(cursor.position, Direction3d::Y, radius, Color::RED);
+
+    index.query_cells(cursor.position, radius, |cell| {
+        gizmos.rect(Vec3::new((cell.0 as f32)*cell_size+cell_size*0.5, (cell.1 as f32)*cell_size, (cell.2 as f32)*cell_size+cell_size*0.5), Quat::from_axis_angle(Vec3::X, PI*0.5), Vec2::new(cell_size, cell_size), Color::WHITE);
+    });
+
+    index.query_within(cursor.position, radius, |_, pos, _| {
+        gizmos.circle(pos, Direction3d::Y, 1.0, Color::RED);
+    });
+    projectile_index.query_within(cursor.position, radius, |_, pos, _| {
+        gizmos.circle(pos, Direction3d::Y, 0.5, Color::RED);
+    });
+
+    // square around the circle, marking the ships in its corners
+    let half = Vec3::new(radius * 2.0, cell_size, radius * 2.0);
+    gizmos.rect(cursor.position, Quat::from_axis_angle(Vec3::X, PI*0.5), Vec2::splat(radius * 4.0), Color::BLUE);
+    index.query_rect(cursor.position - half, cursor.position + half, |_, pos| {
+        if pos.distance_squared(cursor.position) > radius * radius {
+            gizmos.circle(pos, Direction3d::Y, 1.0, Color::BLUE);
+        }
+    });
+
+    // ray from the origin to the cursor, marking every ship it passes through
+    gizmos.line(Vec3::ZERO, cursor.position, Color::YELLOW);
+    let hits = index.raycast_all(Vec3::ZERO, cursor.position, cursor.position.length(), |entity| {
+        radii.get(entity).ok().map(|radius| radius.radius)
+    });
+    for (_, dist) in hits {
+        let point = cursor.position.normalize() * dist;
+        gizmos.circle(point, Direction3d::Y, 1.0, Color::YELLOW);
+    }
+}
+
+/// Starts or stops logging the ships entering and leaving the cell under the cursor.
+fn toggle_cell_logging(cursor: Res<CursorPosition>, mut index: ResMut<SpatialIndex<Ship>>) {
+    let cell = index.calc_cell(cursor.position);
+    let logged = !index.is_cell_logged(cell);
+    index.log_cell(cell, logged);
+    info!("{} logging ships in cell {:?}", if logged { "started" } else { "stopped" }, cell);
+}
+
+
+
+/// Centers models on their ships, once their scenes have spawned.
+fn adjust_by_aabb(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Transform), With<UnadjustedAABB>>,
+    children: Query<&Children>,
+    bounding_boxes: Query<&Aabb>,
+) {
+    for (entity, mut transform) in &mut query {
+        let mut min = Vec3A::MAX;
+        let mut max = Vec3A::MIN;
+        let mut count = 0;
+        for child in children.iter_descendants(entity) {
+            let Ok(bb) = bounding_boxes.get(child) else { continue };
+            min = min.min(bb.center - bb.half_extents);
+            max = max.max(bb.center + bb.half_extents);
+            count += 1;
+        }
+        if count == 0 {
+            // the scene has not been spawned yet
+            continue;
+        }
+        let center = (min + max) * 0.5;
+        transform.translation = transform.with_translation(Vec3::ZERO).transform_point(Vec3::from(-center));
+        commands.entity(entity).remove::<UnadjustedAABB>();
+    }
+}
+
+fn tint_factions(
+    mut commands: Commands,
+    query: Query<(Entity, &Faction), With<UntintedFaction>>,
+    children: Query<&Children>,
+    mut meshes: Query<&mut Handle<StandardMaterial>, With<Handle<Mesh>>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, faction) in &query {
+        let tint = faction.color().as_rgba_f32();
+        let mut tinted = false;
+        for child in children.iter_descendants(entity) {
+            let Ok(mut material_handle) = meshes.get_mut(child) else { continue };
+            // scene materials are shared between ships, so every ship gets its own copy
+            let mut material = materials.get(&*material_handle).unwrap().clone();
+            let [r, g, b, a] = material.base_color.as_rgba_f32();
+            material.base_color = Color::rgba(r * tint[0], g * tint[1], b * tint[2], a);
+            *material_handle = materials.add(material);
+            tinted = true;
+        }
+        if tinted {
+            commands.entity(entity).remove::<UntintedFaction>();
+        }
+    }
+}
+
+fn adjust_materials(
+    mut query: Query<Entity, With<UnadjustedMaterial>>,
+    children: Query<&Children>,
+    meshes: Query<&Handle<StandardMaterial>, With<Handle<Mesh>>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for entity in &mut query {
+        for child in children.iter_descendants(entity) {
+            if let Ok(material_handle) = meshes.get(child) {
+                let material = materials.get_mut(material_handle).unwrap();
+                material.unlit = true;
+            }
+        }
+    }
+}
+
+
+
+/// The flocking simulation itself, shared by the windowed game and headless runs. It runs in
+/// `FixedUpdate`, so the same starting state and orders always play out the same way.
+struct FlockingPlugin {
+    /// Simulation ticks per second.
+    tick_rate: f64,
+    /// Seed of the `SimRng`.
+    seed: u64,
+    avoidance: bool,
+    volumetric: bool,
+    index_backend: SpatialIndexBackend,
+    /// Cells of the ships' spatial index to log ships entering and leaving.
+    logged_cells: Vec<Cell>,
+}
+
+impl Plugin for FlockingPlugin {
+    fn build(&self, app: &mut App) {
+        let index = SpatialIndexPlugin::<Ship>::new(SHIP_CELL_SIZE, self.volumetric)
+            .with_backend(self.index_backend)
+            .with_logged_cells(self.logged_cells.iter().copied());
+        app.add_plugins(index)
+            .insert_resource(Time::<Fixed>::from_hz(self.tick_rate))
+            .insert_resource(SimRng::new(self.seed))
+            .insert_resource(CursorPosition {
+                position: Vec3::ZERO,
+            })
+            .insert_resource(FlightMode {
+                volumetric: self.volumetric,
+            })
+            .init_resource::<FlockingParams>()
+            .insert_resource(Avoidance {
+                enabled: self.avoidance,
+                ..default()
+            })
+            .add_systems(
+                FixedUpdate,
+                (
+                    calc_acceleration,
+                    apply_acceleration.after(calc_acceleration).run_if(avoidance_disabled),
+                    // only one of the two runs in a tick, so they never race
+                    avoid_collisions.after(calc_acceleration).run_if(avoidance_enabled).ambiguous_with(apply_acceleration),
+                    move_by_velocity.after(apply_acceleration).after(avoid_collisions).before(SpatialIndexSet),
+                    advance_orders.after(move_by_velocity).before(SpatialIndexSet),
+                ),
+            );
+    }
+}
+
+/// Command line options.
+pub struct Options {
+    /// Run the simulation without a window and exit after `ticks` frames.
+    headless: bool,
+    ticks: u32,
+    /// Run the headless simulation twice, and fail unless both end with identical ship positions.
+    verify_determinism: bool,
+    /// Check that every asset the scenario refers to is present, and fail if any is missing.
+    check_assets: bool,
+    tick_rate: f64,
+    /// Seed of the simulation's random numbers. Without `--seed`, the scenario's seed, or a random one.
+    seed: Option<u64>,
+    /// Path of the scenario in the assets directory.
+    scenario: String,
+    avoidance: bool,
+    volumetric: bool,
+    /// Index ships in a dense grid rebuilt every frame, instead of a hash map.
+    dense_grid: bool,
+    /// Cells given as `x,y,z` to log ships entering and leaving.
+    log_cells: Vec<Cell>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            headless: false,
+            ticks: 600,
+            verify_determinism: false,
+            check_assets: false,
+            tick_rate: DEFAULT_TICK_RATE,
+            seed: None,
+            scenario: DEFAULT_SCENARIO.to_string(),
+            avoidance: false,
+            volumetric: false,
+            dense_grid: false,
+            log_cells: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args() -> Self {
+        Self::parse_args(USAGE, |_| true)
+    }
+
+    /// The options of the `bench` binary, which only takes those that apply to its headless runs.
+    pub fn bench_from_args() -> Self {
+        Self::parse_args(BENCH_USAGE, |arg| BENCH_ARGS.contains(&arg))
+    }
+
+    /// Parses the command line, exiting with `usage` on arguments that don't parse or `accepts`
+    /// rejects.
+    fn parse_args(usage: &str, accepts: impl Fn(&str) -> bool) -> Self {
+        let usage_error = |message: &str| -> ! { usage_error(usage, message) };
+        let mut options = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if !accepts(&arg) {
+                usage_error(&format!("unknown argument: {}", arg));
+            }
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--verify-determinism" => options.verify_determinism = true,
+                "--check-assets" => options.check_assets = true,
+                "--avoidance" => options.avoidance = true,
+                "--3d" => options.volumetric = true,
+                "--dense-grid" => options.dense_grid = true,
+                "--ticks" => {
+                    options.ticks = args.next().and_then(|t| t.parse().ok())
+                        .unwrap_or_else(|| usage_error("--ticks expects a number"));
+                }
+                "--tick-rate" => {
+                    // `Time::<Fixed>::from_hz` panics unless the timestep is a valid, non-zero `Duration`
+                    options.tick_rate = args.next().and_then(|t| t.parse().ok())
+                        .filter(|rate: &f64| Duration::try_from_secs_f64(1.0 / rate).is_ok_and(|step| !step.is_zero()))
+                        .unwrap_or_else(|| usage_error("--tick-rate expects a positive, finite number of ticks per second"));
+                }
+                "--seed" => {
+                    options.seed = Some(args.next().and_then(|s| s.parse().ok())
+                        .unwrap_or_else(|| usage_error("--seed expects a number")));
+                }
+                "--scenario" => options.scenario = args.next().unwrap_or_else(|| usage_error("--scenario expects a path")),
+                "--log-cell" => {
+                    let cell = args.next().and_then(|c| parse_cell(&c))
+                        .unwrap_or_else(|| usage_error("--log-cell expects a cell as x,y,z"));
+                    options.log_cells.push(cell);
+                }
+                _ => usage_error(&format!("unknown argument: {}", arg)),
+            }
+        }
+        options
+    }
+
+    fn flocking_plugin(&self) -> FlockingPlugin {
+        let index_backend = if self.dense_grid {
+            SpatialIndexBackend::DenseGrid { min: -DENSE_GRID_HALF_EXTENTS, max: DENSE_GRID_HALF_EXTENTS }
+        } else {
+            SpatialIndexBackend::HashMap
+        };
+        FlockingPlugin {
+            tick_rate: self.tick_rate,
+            seed: self.seed.unwrap_or_else(rand::random),
+            avoidance: self.avoidance,
+            volumetric: self.volumetric,
+            index_backend,
+            logged_cells: self.log_cells.clone(),
+        }
+    }
+}
+
+const USAGE: &str = "\
+usage: spacerust [options]
+  --headless             run without a window, then print a summary
+  --ticks N              number of ticks to run headlessly (600)
+  --verify-determinism   run headlessly twice and compare the results
+  --check-assets         check that every asset the scenario refers to is present
+  --tick-rate HZ         simulation ticks per second (60)
+  --seed N               seed of the simulation's random numbers
+  --scenario PATH        scenario in the assets directory (default.scenario.ron)
+  --avoidance            avoid collisions between ships
+  --3d                   fly in a volume instead of the y=0 plane
+  --dense-grid           index ships in a dense grid instead of a hash map
+  --log-cell X,Y,Z       log ships entering and leaving a cell";
+
+/// The arguments of the `bench` binary, out of those `Options::from_args` takes.
+const BENCH_ARGS: &[&str] = &["--tick-rate", "--seed", "--scenario", "--avoidance", "--3d"];
+
+const BENCH_USAGE: &str = "\
+usage: bench [options]
+  --tick-rate HZ         simulation ticks per second (60)
+  --seed N               seed of the simulation's random numbers
+  --scenario PATH        scenario in the assets directory (default.scenario.ron)
+  --avoidance            avoid collisions between ships
+  --3d                   fly in a volume instead of the y=0 plane";
+
+/// Reports a bad command line along with `usage`, and exits.
+fn usage_error(usage: &str, message: &str) -> ! {
+    eprintln!("{}\n{}", message, usage);
+    std::process::exit(2);
+}
+
+fn parse_cell(text: &str) -> Option<Cell> {
+    let mut coords = text.split(',').map(|c| c.trim().parse::<i32>());
+    match (coords.next(), coords.next(), coords.next(), coords.next()) {
+        (Some(Ok(x)), Some(Ok(y)), Some(Ok(z)), None) => Some((x, y, z)),
+        _ => None,
+    }
+}
+
+/// Runs the game, or whichever headless mode `options` asks for.
+pub fn run(options: Options) {
+    if options.check_assets {
+        if !asset_fallback::check_assets(&options.scenario) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if options.verify_determinism {
+        if !headless::verify_determinism(&options) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if options.headless {
+        headless::run(&options);
+        return;
+    }
+
+    App::new()
+        .add_plugins(DefaultPlugins.set(
+            WindowPlugin {
+                primary_window: Some(Window {
+                    resizable: false,
+                    mode: WindowMode::BorderlessFullscreen,
+                    ..default()
+                }),
+                ..default()
+            }
+        ))
+        .add_plugins(CameraControllerPlugin)
+        .add_plugins(options.flocking_plugin())
+        .add_plugins(FlockingParamsPlugin)
+        .add_plugins(CombatPlugin)
+        .add_plugins(SelectionPlugin)
+        .add_plugins(OrdersPlugin)
+        .add_plugins(SpatialIndexReadoutPlugin)
+        .add_plugins(InterpolationPlugin)
+        .add_plugins(ShipClassPlugin)
+        .add_plugins(AssetFallbackPlugin)
+        .add_plugins(ScenarioPlugin {
+            path: options.scenario.clone(),
+            seed_given: options.seed.is_some(),
+        })
+        .add_systems(Startup, setup)
+        .add_systems(
+            Update,
+            (
+                toggle_pause.run_if(input_just_pressed(KeyCode::Space)),
+                toggle_avoidance.run_if(input_just_pressed(KeyCode::KeyV)),
+                toggle_cell_logging.run_if(input_just_pressed(KeyCode::KeyL)),
+                draw_axes,
+                adjust_by_aabb,
+                skybox_system.run_if(resource_exists::<SkyboxResource>),
+                adjust_materials,
+                tint_factions,
+                draw_projectiles,
+                test_spatial_index,
+                update_cursor_ground_plane_position,
+                close_on_esc
+            ),
+        )
+        .run();
+}
+
+
+
+/// Spawns the camera and lighting. Everything else comes from the scenario.
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        Camera3dBundle::default(),
+        CameraController {
+            // the left button is used for selecting ships
+            mouse_key_cursor_grab: MouseButton::Middle,
+            ..default()
+        },
+    ));
+
+    // ambient light
+    // NOTE: The ambient light is used to scale how bright the environment map is so with a bright
+    // environment map, use an appropriate color and brightness to match
+    commands.insert_resource(AmbientLight {
+        color: Color::rgb_u8(210, 220, 240),
+        brightness: 100.0,
+    });
+}
+
+
+fn spawn_sun(commands: &mut Commands, scene: Handle<Scene>, body: &CelestialBody, light: LightSettings) {
+    let (r, g, b) = light.color;
+    commands.spawn(PointLightBundle {
+        transform: Transform::from_translation(body.position),
+        point_light: PointLight {
+            range: light.range,
+            intensity: light.intensity,
+            radius: light.radius,
+            color: Color::rgb(r, g, b),
+            shadows_enabled: light.shadows,
+            ..default()
+        },
+        ..default()
+    }).with_children(|parent| {
+        parent.spawn((
+            UnadjustedMaterial,
+            SceneBundle {
+                scene,
+                transform: Transform::from_translation(body.model_offset).with_scale(Vec3::ONE * body.scale),
+                ..default()
+            }
+        ));
+    });
+}
+
+fn spawn_model(commands: &mut Commands, scene: Handle<Scene>, body: &CelestialBody) {
+    commands.spawn(SceneBundle {
+        scene,
+        transform: Transform::from_translation(body.position + body.model_offset).with_scale(Vec3::ONE * body.scale),
+        ..default()
+    });
+}
+
+
+fn spawn_ship(commands: &mut Commands, class: &ShipClass, scene: Handle<Scene>, position: Vec3, velocity: Vec3, faction: Faction) {
+    let rotation = Quat::from_axis_angle(Vec3::Y, class.model.yaw_degrees.to_radians());
+    spawn_ship_body(commands, class, position, velocity, faction).insert(UntintedFaction).with_children(|parent| {
+        parent.spawn((
+            UnadjustedAABB,
+            SceneBundle {
+                scene,
+                transform: Transform::from_rotation(rotation).with_scale(Vec3::ONE * class.model.scale),
+                ..default()
+            }
+        ));
+    });
+}
+
+/// Spawns the simulated part of a ship, without any model attached.
+fn spawn_ship_body<'a>(commands: &'a mut Commands, class: &ShipClass, position: Vec3, velocity: Vec3, faction: Faction) -> EntityCommands<'a> {
+    let transform = Transform::from_translation(position);
+    commands.spawn((
+        Ship,
+        faction,
+        OrderQueue::default(),
+        CellAssociation::<Ship>::new(),
+        SimulatedTransform::new(transform),
+        SpatialBundle {
+            transform,
+            ..default()
+        },
+        Acceleration {
+            acceleration: Vec3::ZERO,
+        },
+        Radius {
+            radius: class.radius,
+        },
+        Health {
+            hit_points: class.hit_points,
+        },
+        Armament::new(&class.weapons),
+        Velocity {
+            velocity,
+            max_velocity: class.limits.max_velocity,
+            max_force: class.limits.max_force,
+            turn_speed: class.limits.turn_speed,
+        }
+    ))
+}
+
+
+
+fn skybox_system(
+    asset_server: Res<AssetServer>,
+    failed: Res<FailedAssets>,
+    mut images: ResMut<Assets<Image>>,
+    mut skybox: ResMut<SkyboxResource>,
+    mut cameras: Query<&mut Skybox>,
+) {
+    if !skybox.is_loaded && failed.contains(&skybox.image_handle) {
+        warn!("using a generated starfield for the skybox");
+        skybox.is_loaded = true;
+        skybox.image_handle = images.add(starfield_cubemap());
+        for mut camera_skybox in &mut cameras {
+            camera_skybox.image = skybox.image_handle.clone();
+        }
+    }
+    if !skybox.is_loaded && asset_server.load_state(&skybox.image_handle) == LoadState::Loaded {
+        skybox.is_loaded = true;
+        let image = images.get_mut(&skybox.image_handle).unwrap();
+        // NOTE: PNGs do not have any metadata that could indicate they contain a cubemap texture,
+        // so they appear as one texture. The following code reconfigures the texture as necessary.
+        if image.texture_descriptor.array_layer_count() == 1 {
+            image.reinterpret_stacked_2d_as_array(image.height() / image.width());
+            image.texture_view_descriptor = Some(TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::Cube),
+                ..default()
+            });
+        }
+    }
+}
+
+
+fn toggle_pause(mut time: ResMut<Time<Virtual>>) {
+    if time.is_paused() {
+        time.unpause();
+    } else {
+        time.pause();
+    }
+}
+
+fn update_cursor_ground_plane_position(
+    mut cursor: ResMut<CursorPosition>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+) {
+    let (camera, camera_transform) = q_camera.single();
+    let window = q_window.single();
+    let Some(cursor_position) = window.cursor_position() else {
+        return;
+    };
+    if let Some(position) = ground_plane_position(camera, camera_transform, cursor_position) {
+        cursor.position = position;
+    }
+}
+
+/// Projects a point on the screen onto the y=0 plane.
+fn ground_plane_position(camera: &Camera, camera_transform: &GlobalTransform, screen_position: Vec2) -> Option<Vec3> {
+    let plane = Plane3d::new(Vec3::Y);
+    let ray = camera.viewport_to_world(camera_transform, screen_position)?;
+    let distance = ray.intersect_plane(Vec3::ZERO, plane)?;
+    Some(ray.get_point(distance))
+}
@@ -0,0 +1,188 @@
+//! Orders given to selected ships.
+//!
+//! Right-click moves, shift-right-click queues a waypoint, P followed by a right-click patrols
+//! between the ship and the clicked point, H holds position and X stops.
+
+use crate::*;
+use crate::formation::*;
+use crate::selection::Selected;
+use std::collections::VecDeque;
+
+/// Ships closer than this to the destination of their move order, and slower than
+/// `ARRIVAL_SPEED`, have reached it.
+const ARRIVAL_DISTANCE: f32 = 2.0;
+const ARRIVAL_SPEED: f32 = 0.5;
+
+/// Patrolling ships move on to the next point once they are this close to the current one.
+const PATROL_POINT_DISTANCE: f32 = 5.0;
+
+/// Distance between formation slots, relative to the separation radius.
+const FORMATION_SPACING: f32 = 1.2;
+
+pub struct OrdersPlugin;
+
+impl Plugin for OrdersPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Formation>()
+            .init_resource::<PendingPatrol>()
+            .add_systems(
+                Update,
+                (
+                    select_formation,
+                    start_patrol.run_if(input_just_pressed(KeyCode::KeyP)),
+                    issue_move_orders.run_if(input_just_pressed(MouseButton::Right)).after(start_patrol),
+                    hold_position.run_if(input_just_pressed(KeyCode::KeyH)),
+                    stop.run_if(input_just_pressed(KeyCode::KeyX)),
+                    draw_orders,
+                ),
+            );
+    }
+}
+
+#[derive(Clone)]
+pub enum Order {
+    /// Steer to the point and come to a stop there.
+    Move(Vec3),
+    /// Visit the points in a loop, forever. `next` is the index of the point currently headed for.
+    Patrol { points: Vec<Vec3>, next: usize },
+    /// Stay at the point without flocking, returning to it if pushed away.
+    HoldPosition(Vec3),
+}
+
+impl Order {
+    /// The point the ship is currently heading for.
+    pub fn target(&self) -> Vec3 {
+        match self {
+            Order::Move(target) => *target,
+            Order::Patrol { points, next } => points[*next],
+            Order::HoldPosition(position) => *position,
+        }
+    }
+}
+
+/// Orders of a ship, executed front to back. Ships without orders just flock.
+#[derive(Component, Default)]
+pub struct OrderQueue {
+    pub orders: VecDeque<Order>,
+}
+
+impl OrderQueue {
+    pub fn current(&self) -> Option<&Order> {
+        self.orders.front()
+    }
+
+    /// Where the ship will be once every queued order is done, where that is known.
+    fn final_position(&self) -> Option<Vec3> {
+        match self.orders.back()? {
+            Order::Move(target) => Some(*target),
+            Order::HoldPosition(position) => Some(*position),
+            // patrols never end
+            Order::Patrol { .. } => None,
+        }
+    }
+}
+
+/// Set by pressing P: the next right-click issues a patrol instead of a move.
+#[derive(Resource, Default)]
+struct PendingPatrol(bool);
+
+fn start_patrol(mut pending: ResMut<PendingPatrol>) {
+    pending.0 = true;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn issue_move_orders(
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    formation: Res<Formation>,
+    params: Res<FlockingParams>,
+    mut pending_patrol: ResMut<PendingPatrol>,
+    mut selected: Query<(&Transform, &mut OrderQueue), With<Selected>>,
+) {
+    let patrol = std::mem::take(&mut pending_patrol.0);
+    let (camera, camera_transform) = q_camera.single();
+    let Some(cursor_position) = q_window.single().cursor_position() else {
+        return;
+    };
+    let Some(target) = ground_plane_position(camera, camera_transform, cursor_position) else {
+        return;
+    };
+    let append = key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    // queued orders start from where the ship will be once its current orders are done
+    let positions: Vec<Vec3> = selected
+        .iter()
+        .map(|(transform, queue)| {
+            let queued = if append { queue.final_position() } else { None };
+            queued.unwrap_or(transform.translation)
+        })
+        .collect();
+    // slots are spaced so that ships in formation don't push each other apart
+    let spacing = params.separation.radius * FORMATION_SPACING;
+    let targets = formation_targets(*formation, &positions, target, spacing);
+
+    for (((_, mut queue), start), target) in selected.iter_mut().zip(positions).zip(targets) {
+        if !append {
+            queue.orders.clear();
+        }
+        queue.orders.push_back(if patrol {
+            Order::Patrol { points: vec![start, target], next: 1 }
+        } else {
+            Order::Move(target)
+        });
+    }
+}
+
+fn hold_position(mut selected: Query<(&Transform, &mut OrderQueue), With<Selected>>) {
+    for (transform, mut queue) in &mut selected {
+        queue.orders.clear();
+        queue.orders.push_back(Order::HoldPosition(transform.translation));
+    }
+}
+
+fn stop(mut selected: Query<&mut OrderQueue, With<Selected>>) {
+    for mut queue in &mut selected {
+        queue.orders.clear();
+    }
+}
+
+/// Pops reached move orders and moves patrols on to their next point.
+pub fn advance_orders(mut query: Query<(&Transform, &Velocity, &mut OrderQueue)>) {
+    for (transform, vel, mut queue) in &mut query {
+        match queue.orders.front_mut() {
+            Some(Order::Move(target)) => {
+                if transform.translation.distance(*target) < ARRIVAL_DISTANCE && vel.velocity.length() < ARRIVAL_SPEED {
+                    queue.orders.pop_front();
+                }
+            }
+            Some(Order::Patrol { points, next }) => {
+                if transform.translation.distance(points[*next]) < PATROL_POINT_DISTANCE {
+                    *next = (*next + 1) % points.len();
+                }
+            }
+            Some(Order::HoldPosition(_)) | None => {}
+        }
+    }
+}
+
+fn draw_orders(query: Query<(&Transform, &OrderQueue), With<Selected>>, mut gizmos: Gizmos) {
+    for (transform, queue) in &query {
+        let mut from = transform.translation;
+        for order in &queue.orders {
+            match order {
+                Order::Move(target) => {
+                    gizmos.line(from, *target, Color::GREEN);
+                    from = *target;
+                }
+                Order::Patrol { points, next } => {
+                    gizmos.line(from, points[*next], Color::YELLOW);
+                    gizmos.linestrip(points.iter().chain(points.first()).copied(), Color::YELLOW);
+                }
+                Order::HoldPosition(position) => {
+                    gizmos.circle(*position, Direction3d::Y, 2.0, Color::ORANGE_RED);
+                }
+            }
+        }
+    }
+}
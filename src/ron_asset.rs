@@ -0,0 +1,68 @@
+//! Loading of game data assets written in RON.
+
+use bevy::{
+    asset::{io::{file::FileAssetReader, Reader}, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::de::DeserializeOwned;
+use std::{marker::PhantomData, path::PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RonAssetError {
+    #[error("could not read asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse asset: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+/// Asset loader for any asset type that can be deserialized from RON.
+pub struct RonAssetLoader<A> {
+    extensions: &'static [&'static str],
+    _marker: PhantomData<fn() -> A>,
+}
+
+impl<A> RonAssetLoader<A> {
+    pub fn new(extensions: &'static [&'static str]) -> Self {
+        Self {
+            extensions,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<A: Asset + DeserializeOwned> AssetLoader for RonAssetLoader<A> {
+    type Asset = A;
+    type Settings = ();
+    type Error = RonAssetError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<A, RonAssetError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+}
+
+/// Reads a RON asset straight from the assets directory, for when there is no `AssetServer`.
+pub fn load_ron_file<T: DeserializeOwned>(path: &str) -> Result<T, RonAssetError> {
+    let bytes = std::fs::read(asset_file_path(path))?;
+    Ok(ron::de::from_bytes(&bytes)?)
+}
+
+/// Where an asset path, without any `#label`, points to on disk.
+pub fn asset_file_path(path: &str) -> PathBuf {
+    let path = path.split_once('#').map_or(path, |(file, _label)| file);
+    FileAssetReader::get_base_path().join("assets").join(path)
+}
@@ -0,0 +1,243 @@
+//! Scenarios: the ships, celestial bodies, camera and skybox the universe starts out with, loaded
+//! from `*.scenario.ron` assets.
+
+use crate::*;
+use crate::ron_asset::*;
+use serde::Deserialize;
+
+pub const DEFAULT_SCENARIO: &str = "default.scenario.ron";
+
+#[derive(Asset, Resource, TypePath, Clone, Deserialize)]
+pub struct Scenario {
+    /// Seed of the `SimRng`, unless one is given with `--seed`. Random when neither is.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub camera: CameraStart,
+    #[serde(default)]
+    pub skybox: Option<SkyboxSettings>,
+    pub ship_groups: Vec<ShipGroup>,
+    #[serde(default)]
+    pub bodies: Vec<CelestialBody>,
+}
+
+impl Scenario {
+    pub fn ship_count(&self) -> usize {
+        self.ship_groups.iter().map(|group| group.count).sum()
+    }
+
+    /// The names of the ship classes the scenario uses, each once.
+    pub fn class_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for group in &self.ship_groups {
+            if !names.contains(&group.class.as_str()) {
+                names.push(&group.class);
+            }
+        }
+        names
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CameraStart {
+    pub position: Vec3,
+    pub look_at: Vec3,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SkyboxSettings {
+    /// Cubemap image, with the faces stacked vertically.
+    pub image: String,
+    pub brightness: f32,
+}
+
+/// Ships of one class and faction, scattered over a region.
+#[derive(Clone, Deserialize)]
+pub struct ShipGroup {
+    /// Name of the `ShipClass`.
+    pub class: String,
+    pub count: usize,
+    pub faction: u8,
+    /// Ships start at uniformly random positions in this box. The y range is ignored unless ships
+    /// fly in a volume.
+    pub region: SpawnRegion,
+    /// Ships start with a uniformly random speed in this range...
+    pub speed: (f32, f32),
+    /// ...along this direction, or in a random one if it isn't given.
+    #[serde(default)]
+    pub heading: Option<Vec3>,
+}
+
+impl ShipGroup {
+    /// Picks the starting position and velocity of a ship of the group.
+    pub fn random_state(&self, rng: &mut impl Rng, mode: FlightMode) -> (Vec3, Vec3) {
+        let (min, max) = (self.region.min, self.region.max);
+        let mut position = Vec3::new(random_between(rng, min.x, max.x), 0.0, random_between(rng, min.z, max.z));
+        let speed = random_between(rng, self.speed.0, self.speed.1);
+        let mut direction = self.heading.unwrap_or_else(|| Vec3::new(rng.gen::<f32>() - 0.5, 0.0, rng.gen::<f32>() - 0.5));
+        if mode.volumetric {
+            position.y = random_between(rng, min.y, max.y);
+            if self.heading.is_none() {
+                direction.y = rng.gen::<f32>() - 0.5;
+            }
+        } else {
+            direction.y = 0.0;
+        }
+        (position, direction.normalize_or_zero() * speed)
+    }
+}
+
+/// Unlike `gen_range`, doesn't panic when a scenario gets the bounds the wrong way round.
+fn random_between(rng: &mut impl Rng, a: f32, b: f32) -> f32 {
+    a + (b - a) * rng.gen::<f32>()
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct SpawnRegion {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+/// A planet, or with a light, a star.
+#[derive(Clone, Deserialize)]
+pub struct CelestialBody {
+    pub model: String,
+    pub position: Vec3,
+    pub scale: f32,
+    /// Offset of the model from the body's position.
+    #[serde(default)]
+    pub model_offset: Vec3,
+    #[serde(default)]
+    pub light: Option<LightSettings>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct LightSettings {
+    pub range: f32,
+    pub intensity: f32,
+    pub radius: f32,
+    pub color: (f32, f32, f32),
+    pub shadows: bool,
+}
+
+/// Loads the scenario at `path` through the `AssetServer`, and sets up the universe it describes
+/// once it and its ship classes have loaded.
+pub struct ScenarioPlugin {
+    pub path: String,
+    /// Whether the `SimRng` was seeded from the command line, overriding the scenario's seed.
+    pub seed_given: bool,
+}
+
+#[derive(Resource)]
+struct ScenarioState {
+    handle: Handle<Scenario>,
+    seed_given: bool,
+    /// The ship classes of the scenario, once it has loaded.
+    classes: Option<Vec<(String, Handle<ShipClass>)>>,
+    spawned: bool,
+}
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        let path = self.path.clone();
+        let seed_given = self.seed_given;
+        app.init_asset::<Scenario>()
+            .register_asset_loader(RonAssetLoader::<Scenario>::new(&["scenario.ron"]))
+            .add_systems(Startup, move |mut commands: Commands, asset_server: Res<AssetServer>| {
+                commands.insert_resource(ScenarioState {
+                    handle: asset_server.load(path.clone()),
+                    seed_given,
+                    classes: None,
+                    spawned: false,
+                });
+            })
+            .add_systems(Update, spawn_scenario.run_if(|state: Res<ScenarioState>| !state.spawned));
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_scenario(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    scenarios: Res<Assets<Scenario>>,
+    class_assets: Res<Assets<ShipClass>>,
+    mut state: ResMut<ScenarioState>,
+    mode: Res<FlightMode>,
+    mut rng: ResMut<SimRng>,
+    mut cameras: Query<(Entity, &mut Transform), With<Camera3d>>,
+) {
+    if asset_server.load_state(&state.handle) == LoadState::Failed {
+        error!("scenario {:?} failed to load", state.handle.path());
+        state.spawned = true;
+        return;
+    }
+    let Some(scenario) = scenarios.get(&state.handle) else {
+        return;
+    };
+    let Some(class_handles) = &state.classes else {
+        state.classes = Some(scenario.class_names().into_iter()
+            .map(|name| (name.to_string(), asset_server.load(ShipClass::path(name))))
+            .collect());
+        return;
+    };
+    let mut classes = ShipClasses::default();
+    for (name, handle) in class_handles {
+        match class_assets.get(handle) {
+            Some(class) => classes.insert(name.clone(), class.clone()),
+            None if asset_server.load_state(handle) == LoadState::Failed => {
+                error!("ship class {} failed to load, so its ships are left out", name);
+            }
+            None => return,
+        }
+    }
+    state.spawned = true;
+
+    if let (false, Some(seed)) = (state.seed_given, scenario.seed) {
+        *rng = SimRng::new(seed);
+    }
+    rng.log_seed();
+
+    for (camera, mut transform) in &mut cameras {
+        *transform = Transform::from_translation(scenario.camera.position).looking_at(scenario.camera.look_at, Vec3::Y);
+        if let Some(skybox) = &scenario.skybox {
+            let image_handle = asset_server.load(skybox.image.clone());
+            commands.entity(camera).insert(Skybox {
+                image: image_handle.clone(),
+                brightness: skybox.brightness,
+            });
+            commands.insert_resource(SkyboxResource {
+                is_loaded: false,
+                image_handle,
+            });
+        }
+    }
+
+    for group in &scenario.ship_groups {
+        let Some(class) = classes.get(&group.class) else { continue };
+        let scene = asset_server.load(class.model.scene.clone());
+        for _ in 0..group.count {
+            let (position, velocity) = group.random_state(&mut *rng, *mode);
+            spawn_ship(&mut commands, class, scene.clone(), position, velocity, Faction { id: group.faction });
+        }
+    }
+    commands.insert_resource(classes);
+
+    for body in &scenario.bodies {
+        let scene = asset_server.load(body.model.clone());
+        match body.light {
+            Some(light) => spawn_sun(&mut commands, scene, body, light),
+            None => spawn_model(&mut commands, scene, body),
+        }
+    }
+}
+
+/// Spawns the simulated part of every ship of the scenario, without models, drawing their
+/// starting states in the same order as `ScenarioPlugin` does.
+pub fn spawn_ship_bodies(commands: &mut Commands, scenario: &Scenario, classes: &ShipClasses, rng: &mut SimRng, mode: FlightMode) {
+    for group in &scenario.ship_groups {
+        let Some(class) = classes.get(&group.class) else { continue };
+        for _ in 0..group.count {
+            let (position, velocity) = group.random_state(rng, mode);
+            spawn_ship_body(commands, class, position, velocity, Faction { id: group.faction });
+        }
+    }
+}
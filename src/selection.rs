@@ -0,0 +1,164 @@
+//! RTS-style selection of ships: click to pick a single ship, drag to box-select, shift to add.
+
+use crate::*;
+use bevy::render::primitives::Frustum;
+
+/// Clicks closer than this (in world units) to a ship pick it, even if it is smaller.
+const PICK_RADIUS: f32 = 5.0;
+
+/// How far from the camera ships can be picked.
+const PICK_DISTANCE: f32 = 5000.0;
+
+/// Cursor movement (in logical pixels) before a press is treated as a drag instead of a click.
+const DRAG_THRESHOLD: f32 = 4.0;
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DragState>()
+            .add_systems(Startup, spawn_selection_box)
+            .add_systems(
+                Update,
+                (
+                    update_selection,
+                    update_selection_box.after(update_selection),
+                    draw_selection,
+                ),
+            );
+    }
+}
+
+#[derive(Component)]
+#[component(storage = "SparseSet")]
+pub struct Selected;
+
+#[derive(Resource, Default)]
+struct DragState {
+    /// Screen position where the left button was pressed, while it is held.
+    start: Option<Vec2>,
+}
+
+#[derive(Component)]
+struct SelectionBox;
+
+fn spawn_selection_box(mut commands: Commands) {
+    commands.spawn((
+        SelectionBox,
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                border: UiRect::all(Val::Px(1.0)),
+                ..default()
+            },
+            border_color: BorderColor(Color::GREEN),
+            background_color: BackgroundColor(Color::rgba(0.0, 1.0, 0.0, 0.1)),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+    ));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_selection(
+    mut commands: Commands,
+    mut drag: ResMut<DragState>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    index: Res<SpatialIndex<Ship>>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    ships: Query<(Entity, &Transform, &Radius), With<Ship>>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    let cursor_position = q_window.single().cursor_position();
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        drag.start = cursor_position;
+    }
+    if !mouse_button_input.just_released(MouseButton::Left) {
+        return;
+    }
+    // the drag ends even when the button is released outside the window, but selects nothing
+    let (Some(start), Some(cursor_position)) = (drag.start.take(), cursor_position) else {
+        return;
+    };
+
+    if !key_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        for entity in &selected {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+
+    let (camera, camera_transform) = q_camera.single();
+    if start.distance(cursor_position) < DRAG_THRESHOLD {
+        let Some(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
+            return;
+        };
+        let hit = index.raycast(ray.origin, *ray.direction, PICK_DISTANCE, |entity| {
+            ships.get(entity).ok().map(|(_, _, radius)| radius.radius.max(PICK_RADIUS))
+        });
+        if let Some((entity, _)) = hit {
+            commands.entity(entity).insert(Selected);
+        }
+    } else {
+        let Some(frustum) = selection_frustum(camera, camera_transform, Rect::from_corners(start, cursor_position)) else {
+            return;
+        };
+        index.query_frustum(&frustum, |entity, _| {
+            if ships.contains(entity) {
+                commands.entity(entity).insert(Selected);
+            }
+        });
+    }
+}
+
+/// The part of the camera's frustum behind `rect`, in logical viewport pixels.
+fn selection_frustum(camera: &Camera, camera_transform: &GlobalTransform, rect: Rect) -> Option<Frustum> {
+    let size = camera.logical_viewport_size()?;
+    // the rectangle in normalized device coordinates, where y points up
+    let min = Vec2::new(rect.min.x / size.x * 2.0 - 1.0, 1.0 - rect.max.y / size.y * 2.0);
+    let max = Vec2::new(rect.max.x / size.x * 2.0 - 1.0, 1.0 - rect.min.y / size.y * 2.0);
+    // scales clip space so that the rectangle fills it
+    let scale = 2.0 / (max - min);
+    let center = (min + max) * 0.5;
+    let crop = Mat4::from_cols(
+        Vec4::new(scale.x, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, scale.y, 0.0, 0.0),
+        Vec4::Z,
+        (-scale * center).extend(0.0).extend(1.0),
+    );
+    let view_projection = crop * camera.projection_matrix() * camera_transform.compute_matrix().inverse();
+    Some(Frustum::from_view_projection(&view_projection))
+}
+
+fn update_selection_box(
+    drag: Res<DragState>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    mut q_box: Query<(&mut Style, &mut Visibility), With<SelectionBox>>,
+) {
+    let (mut style, mut visibility) = q_box.single_mut();
+    let cursor_position = q_window.single().cursor_position();
+    let (Some(start), Some(end)) = (drag.start, cursor_position) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    let rect = Rect::from_corners(start, end);
+    style.left = Val::Px(rect.min.x);
+    style.top = Val::Px(rect.min.y);
+    style.width = Val::Px(rect.width());
+    style.height = Val::Px(rect.height());
+    *visibility = if rect.width() < DRAG_THRESHOLD && rect.height() < DRAG_THRESHOLD {
+        Visibility::Hidden
+    } else {
+        Visibility::Visible
+    };
+}
+
+fn draw_selection(
+    query: Query<(&Transform, &Radius), With<Selected>>,
+    mut gizmos: Gizmos,
+) {
+    for (transform, radius) in &query {
+        gizmos.circle(transform.translation, Direction3d::Y, radius.radius * 1.5, Color::GREEN);
+    }
+}
@@ -0,0 +1,88 @@
+//! Ship classes: the model, handling, size, toughness and weapons shared by every ship of a kind,
+//! loaded from `ships/<name>.ship.ron` assets and referred to by name.
+
+use crate::{ron_asset::*, SHIP_CELL_SIZE};
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Asset, TypePath, Clone, Deserialize)]
+pub struct ShipClass {
+    pub model: ShipModel,
+    pub limits: VelocityLimits,
+    /// Bounding radius, used for collision avoidance, hits and picking. Must not exceed the cell
+    /// size of the ships' spatial index, which raycasts only search the neighbouring cells of, or
+    /// it is clamped to it.
+    pub radius: f32,
+    pub hit_points: f32,
+    #[serde(default)]
+    pub weapons: Vec<WeaponStats>,
+}
+
+impl ShipClass {
+    /// Path of the named class in the assets directory.
+    pub fn path(name: &str) -> String {
+        format!("ships/{}.ship.ron", name)
+    }
+
+    /// Describes what is wrong with the radius, if it is larger than the ships' index allows.
+    pub fn radius_problem(&self) -> Option<String> {
+        (self.radius > SHIP_CELL_SIZE)
+            .then(|| format!("radius {} exceeds the cell size of the ships' spatial index, {}", self.radius, SHIP_CELL_SIZE))
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ShipModel {
+    pub scene: String,
+    /// Rotation of the model about the y axis, so that it faces along +z, the way ships fly.
+    pub yaw_degrees: f32,
+    pub scale: f32,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct VelocityLimits {
+    pub max_velocity: f32,
+    pub max_force: f32,
+    pub turn_speed: f32,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct WeaponStats {
+    pub range: f32,
+    /// Seconds between shots.
+    pub cooldown: f32,
+    pub damage: f32,
+    pub projectile_speed: f32,
+}
+
+/// The loaded ship classes, by name.
+#[derive(Resource, Default, Clone)]
+pub struct ShipClasses {
+    classes: HashMap<String, ShipClass>,
+}
+
+impl ShipClasses {
+    /// Adds the class, clamping a radius too large for the ships' index with a warning.
+    pub fn insert(&mut self, name: String, mut class: ShipClass) {
+        if let Some(problem) = class.radius_problem() {
+            warn!("ship class {}: {}, so it is clamped", name, problem);
+            class.radius = SHIP_CELL_SIZE;
+        }
+        self.classes.insert(name, class);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ShipClass> {
+        self.classes.get(name)
+    }
+}
+
+pub struct ShipClassPlugin;
+
+impl Plugin for ShipClassPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ShipClass>()
+            .register_asset_loader(RonAssetLoader::<ShipClass>::new(&["ship.ron"]))
+            .init_resource::<ShipClasses>();
+    }
+}
@@ -0,0 +1,44 @@
+//! The simulation's source of random numbers, seeded so that runs can be replayed.
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+/// Random numbers for everything that affects the simulation, such as the starting ships. Systems
+/// must draw from it in a fixed order, not from inside `par_iter`, or runs with the same seed diverge.
+#[derive(Resource)]
+pub struct SimRng {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Logs the seed, so that the run can be replayed with `--seed`.
+    pub fn log_seed(&self) {
+        info!("simulation seed: {} (replay with --seed {})", self.seed, self.seed);
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
@@ -1,147 +1,1099 @@
-use crate::CursorPosition;
-use bevy::prelude::*;
-use std::{collections::{hash_map::Entry, HashMap}, f32::consts::PI};
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic},
+    math::Affine3A,
+    prelude::*,
+    render::primitives::{Aabb, Frustum, Sphere},
+    utils::get_short_name,
+};
+use std::{
+    any::type_name,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    marker::PhantomData,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
-const CELL_DIM: f32 = 20.0;
+/// Log target of the messages about entities entering and leaving logged cells, so they can be
+/// filtered like any other target, e.g. `RUST_LOG=spatial_index::cells=off`.
+const CELL_LOG_TARGET: &str = "spatial_index::cells";
 
-pub type Cell = (i32, i32);
+/// First component of the paths of every spatial index diagnostic.
+const DIAGNOSTIC_PREFIX: &str = "spatial_index";
 
-fn calc_cell(pos: Vec3) -> Cell {
-    ((pos.x / CELL_DIM).floor() as i32, (pos.z / CELL_DIM).floor() as i32)
+/// Half the height given to cells when frustum culling a flat index, whose cells are unbounded columns.
+const COLUMN_HALF_HEIGHT: f32 = 1.0e6;
+
+/// Returns the distance along the ray at which it enters the sphere, or 0 if it starts inside.
+/// `dir` must be normalized.
+fn ray_sphere(origin: Vec3, dir: Vec3, center: Vec3, radius: f32) -> Option<f32> {
+    let m = origin - center;
+    let b = m.dot(dir);
+    let c = m.length_squared() - radius * radius;
+    if c > 0.0 && b > 0.0 {
+        // outside the sphere and pointing away from it
+        return None;
+    }
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    Some((-b - discriminant.sqrt()).max(0.0))
+}
+
+/// Cell coordinates along x, y and z. The y coordinate is always 0 unless the index is volumetric.
+pub type Cell = (i32, i32, i32);
+
+/// Returns the coordinate of the cell edge in `[c, c + 1]` closest to `center`, or `center` itself if it lies inside.
+fn closest_in_cell(c: i32, center: f32) -> f32 {
+    if ((c + 1) as f32) < center {
+        (c + 1) as f32
+    } else if (c as f32) > center {
+        c as f32
+    } else {
+        center
+    }
+}
+
+fn calc_cell(pos: Vec3, cell_size: f32, volumetric: bool) -> Cell {
+    let y = if volumetric { (pos.y / cell_size).floor() as i32 } else { 0 };
+    ((pos.x / cell_size).floor() as i32, y, (pos.z / cell_size).floor() as i32)
+}
+
+/// How a `SpatialIndex` stores its cells.
+#[derive(Clone, Copy, Debug)]
+pub enum SpatialIndexBackend {
+    /// A hash map of cells, updated incrementally as entities move between them. Suits sparse and
+    /// unbounded worlds.
+    HashMap,
+    /// A flat array of the cells between `min` and `max`, rebuilt every frame with a counting sort.
+    /// Suits crowded, bounded worlds. Entities outside the bounds are kept in a hash map.
+    DenseGrid { min: Vec3, max: Vec3 },
+}
+
+enum Storage {
+    HashMap(HashCells),
+    DenseGrid(DenseGrid),
+}
+
+#[derive(Default)]
+struct HashCells {
+    /// Indexed entities in each cell, with their positions as of the last update.
+    cells: HashMap<Cell, Vec<(Entity, Vec3)>>,
+    /// The cell each indexed entity is stored in, and its slot there, so it can be updated and
+    /// removed without searching.
+    entity_cells: HashMap<Entity, (Cell, usize)>,
+}
+
+impl HashCells {
+    fn insert(&mut self, cell: Cell, entity: Entity, pos: Vec3) {
+        let vec = self.cells.entry(cell).or_default();
+        vec.push((entity, pos));
+        self.entity_cells.insert(entity, (cell, vec.len() - 1));
+    }
+
+    /// Removes the entity, returning the cell it was in.
+    fn remove(&mut self, entity: Entity) -> Option<Cell> {
+        let (cell, slot) = self.entity_cells.remove(&entity)?;
+        if let Entry::Occupied(mut occupied) = self.cells.entry(cell) {
+            let vec = occupied.get_mut();
+            vec.swap_remove(slot);
+            // the last entity of the cell took the removed one's slot
+            if let Some((moved, _)) = vec.get(slot) {
+                self.entity_cells.insert(*moved, (cell, slot));
+            }
+            if vec.is_empty() {
+                occupied.remove_entry();
+            }
+        }
+        Some(cell)
+    }
+
+    fn set_position(&mut self, entity: Entity, pos: Vec3) {
+        if let Some((cell, slot)) = self.entity_cells.get(&entity) {
+            self.cells.get_mut(cell).unwrap()[*slot].1 = pos;
+        }
+    }
+}
+
+struct DenseGrid {
+    /// The first cell of the grid, and the number of cells along x, y and z.
+    origin: Cell,
+    size: [usize; 3],
+    /// Where the entries of each cell start in `entries`, followed by the number of entries.
+    starts: Vec<u32>,
+    /// Indexed entities and their positions, ordered by cell.
+    entries: Vec<(Entity, Vec3)>,
+    /// Entities outside of the grid.
+    outside: HashMap<Cell, Vec<(Entity, Vec3)>>,
+    occupied: usize,
+    /// Buffers reused between rebuilds: the slot of every entity in the grid, and the next free
+    /// entry of each cell.
+    sorting: Vec<(usize, Entity, Vec3)>,
+    next: Vec<u32>,
+}
+
+impl DenseGrid {
+    fn new(min: Cell, max: Cell) -> Self {
+        let size = [
+            (max.0 - min.0 + 1).max(1) as usize,
+            (max.1 - min.1 + 1).max(1) as usize,
+            (max.2 - min.2 + 1).max(1) as usize,
+        ];
+        Self {
+            origin: min,
+            size,
+            starts: vec![0; size[0] * size[1] * size[2] + 1],
+            entries: Vec::new(),
+            outside: HashMap::new(),
+            occupied: 0,
+            sorting: Vec::new(),
+            next: Vec::new(),
+        }
+    }
+
+    fn slot(&self, cell: Cell) -> Option<usize> {
+        let x = usize::try_from(cell.0 - self.origin.0).ok().filter(|x| *x < self.size[0])?;
+        let y = usize::try_from(cell.1 - self.origin.1).ok().filter(|y| *y < self.size[1])?;
+        let z = usize::try_from(cell.2 - self.origin.2).ok().filter(|z| *z < self.size[2])?;
+        Some((y * self.size[2] + z) * self.size[0] + x)
+    }
+
+    fn slot_cell(&self, slot: usize) -> Cell {
+        let x = slot % self.size[0];
+        let z = slot / self.size[0] % self.size[2];
+        let y = slot / self.size[0] / self.size[2];
+        (self.origin.0 + x as i32, self.origin.1 + y as i32, self.origin.2 + z as i32)
+    }
+
+    fn cell(&self, cell: Cell) -> &[(Entity, Vec3)] {
+        match self.slot(cell) {
+            Some(slot) => &self.entries[self.starts[slot] as usize..self.starts[slot + 1] as usize],
+            None => self.outside.get(&cell).map_or(&[], Vec::as_slice),
+        }
+    }
+
+    /// Replaces the contents of the grid with `entities`, sorting them into their cells.
+    fn rebuild(&mut self, entities: impl Iterator<Item = (Entity, Vec3, Cell)>) {
+        self.sorting.clear();
+        self.outside.clear();
+        for (entity, pos, cell) in entities {
+            match self.slot(cell) {
+                Some(slot) => self.sorting.push((slot, entity, pos)),
+                None => self.outside.entry(cell).or_default().push((entity, pos)),
+            }
+        }
+
+        // count the entities in each cell, then turn the counts into start offsets
+        self.starts.fill(0);
+        for (slot, _, _) in &self.sorting {
+            self.starts[slot + 1] += 1;
+        }
+        self.occupied = self.starts.iter().filter(|count| **count > 0).count() + self.outside.len();
+        for i in 1..self.starts.len() {
+            self.starts[i] += self.starts[i - 1];
+        }
+
+        self.next.clear();
+        self.next.extend_from_slice(&self.starts[..self.starts.len() - 1]);
+        self.entries.clear();
+        self.entries.resize(self.sorting.len(), (Entity::PLACEHOLDER, Vec3::ZERO));
+        for (slot, entity, pos) in &self.sorting {
+            self.entries[self.next[*slot] as usize] = (*entity, *pos);
+            self.next[*slot] += 1;
+        }
+    }
 }
 
+/// Indexes the positions of the entities with the marker component `T` and a `CellAssociation<T>`,
+/// so that separate kinds of entities can be kept in separate indices with different cell sizes.
 #[derive(Resource)]
-pub struct SpatialIndex {
-    cells: HashMap<Cell, Vec<Entity>>,
+pub struct SpatialIndex<T: Component> {
+    storage: Storage,
+    /// Edge length of the cells.
+    cell_size: f32,
+    /// When set, cells are cubes and queries are spheres, instead of columns and circles in the xz plane.
+    volumetric: bool,
+    /// Cells whose entities are logged as they enter and leave them.
+    logged_cells: HashSet<Cell>,
+    /// Entities which moved to another cell, and queries made, since the stats were last recorded.
+    /// Queries only borrow the index, and may run in parallel.
+    moves: u32,
+    queries: AtomicU32,
+    marker: PhantomData<T>,
 }
 
-#[derive(Component)]
-#[component(storage = "SparseSet")]
-pub struct HasDirtyCell;
+/// A snapshot of how a `SpatialIndex` is filled and used, recorded every frame as diagnostics.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpatialIndexStats {
+    /// Number of occupied cells.
+    pub cell_count: usize,
+    /// Most and mean entities in an occupied cell.
+    pub max_occupancy: usize,
+    pub mean_occupancy: f32,
+    /// Entities which moved to another cell this frame.
+    pub moves: u32,
+    /// Queries made of the index this frame, including raycasts.
+    pub queries: u32,
+}
 
+/// Marks an entity to be kept in the `SpatialIndex<T>`.
 #[derive(Component)]
-pub struct CellAssociation {
-    cell: Cell,
-    new_cell: Cell,
+pub struct CellAssociation<T: Component> {
+    /// The cell the entity is stored in, `None` until it has been indexed.
+    cell: Option<Cell>,
+    marker: PhantomData<T>,
 }
 
-impl CellAssociation {
+impl<T: Component> CellAssociation<T> {
     pub fn new() -> Self {
         Self {
-            cell: (i32::MIN, i32::MIN),
-            new_cell: (i32::MIN, i32::MIN),
+            cell: None,
+            marker: PhantomData,
         }
     }
 }
 
-impl SpatialIndex {
-    pub fn new() -> Self {
+/// The systems updating every spatial index, in `FixedUpdate` with the rest of the simulation.
+/// Systems moving, spawning or despawning indexed entities should run before it, and systems that
+/// need the index to be current after it. Changes made after it show up in the index at the end of
+/// the tick.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpatialIndexSet;
+
+/// Brings the indices up to date with entities despawned during the tick, and with the hash map
+/// backend also with entities moved or spawned after `SpatialIndexSet`, at its end.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+struct SpatialIndexCleanup;
+
+/// Adds a `SpatialIndex<T>` and the systems keeping it up to date, in `SpatialIndexSet`.
+pub struct SpatialIndexPlugin<T: Component> {
+    cell_size: f32,
+    volumetric: bool,
+    backend: SpatialIndexBackend,
+    logged_cells: Vec<Cell>,
+    marker: PhantomData<T>,
+}
+
+impl<T: Component> SpatialIndexPlugin<T> {
+    pub fn new(cell_size: f32, volumetric: bool) -> Self {
         Self {
-            cells: HashMap::new()
+            cell_size,
+            volumetric,
+            backend: SpatialIndexBackend::HashMap,
+            logged_cells: Vec::new(),
+            marker: PhantomData,
         }
     }
 
-    pub fn query<F: FnMut(Entity)>(&self, pos: Vec3, radius: f32, mut handler: F) {
+    pub fn with_backend(mut self, backend: SpatialIndexBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Logs entities entering and leaving these cells from the start.
+    pub fn with_logged_cells(mut self, cells: impl IntoIterator<Item = Cell>) -> Self {
+        self.logged_cells.extend(cells);
+        self
+    }
+}
+
+impl<T: Component> Plugin for SpatialIndexPlugin<T> {
+    fn build(&self, app: &mut App) {
+        let mut index = SpatialIndex::<T>::new(self.cell_size, self.volumetric, self.backend);
+        for cell in &self.logged_cells {
+            index.log_cell(*cell, true);
+        }
+        let diagnostics = SpatialIndexDiagnostics::<T>::new();
+        for path in diagnostics.paths() {
+            app.register_diagnostic(Diagnostic::new(path.clone()));
+        }
+        app.insert_resource(index)
+            .insert_resource(diagnostics)
+            .add_systems(Last, record_spatial_index_stats::<T>);
+        // entities despawned by commands after the set ran are only gone at the end of the tick
+        match self.backend {
+            SpatialIndexBackend::HashMap => {
+                app.add_systems(
+                    FixedUpdate,
+                    (remove_despawned_from_index::<T>, update_spatial_index::<T>).chain().in_set(SpatialIndexSet),
+                )
+                .add_systems(
+                    FixedLast,
+                    (remove_despawned_from_index::<T>, update_spatial_index::<T>).chain().in_set(SpatialIndexCleanup),
+                );
+            }
+            SpatialIndexBackend::DenseGrid { .. } => {
+                app.add_systems(FixedUpdate, rebuild_spatial_index::<T>.in_set(SpatialIndexSet))
+                    .add_systems(FixedLast, rebuild_after_despawns::<T>.in_set(SpatialIndexCleanup));
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        app.add_systems(FixedLast, check_spatial_index::<T>.after(SpatialIndexCleanup));
+    }
+}
+
+impl<T: Component> SpatialIndex<T> {
+    pub fn new(cell_size: f32, volumetric: bool, backend: SpatialIndexBackend) -> Self {
+        let storage = match backend {
+            SpatialIndexBackend::HashMap => Storage::HashMap(HashCells::default()),
+            SpatialIndexBackend::DenseGrid { min, max } => Storage::DenseGrid(DenseGrid::new(
+                calc_cell(min, cell_size, volumetric),
+                calc_cell(max, cell_size, volumetric),
+            )),
+        };
+        Self {
+            storage,
+            cell_size,
+            volumetric,
+            logged_cells: HashSet::new(),
+            moves: 0,
+            queries: AtomicU32::new(0),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn calc_cell(&self, pos: Vec3) -> Cell {
+        calc_cell(pos, self.cell_size, self.volumetric)
+    }
+
+    /// Number of occupied cells.
+    pub fn cell_count(&self) -> usize {
+        match &self.storage {
+            Storage::HashMap(hash_cells) => hash_cells.cells.len(),
+            Storage::DenseGrid(grid) => grid.occupied,
+        }
+    }
+
+    /// Summarizes the occupied cells, and the moves and queries since the stats were last recorded.
+    pub fn stats(&self) -> SpatialIndexStats {
+        let mut stats = SpatialIndexStats {
+            cell_count: self.cell_count(),
+            moves: self.moves,
+            queries: self.queries.load(Ordering::Relaxed),
+            ..default()
+        };
+        let mut total = 0;
+        self.for_each_cell(|_, entities| {
+            stats.max_occupancy = stats.max_occupancy.max(entities.len());
+            total += entities.len();
+        });
+        if stats.cell_count > 0 {
+            stats.mean_occupancy = total as f32 / stats.cell_count as f32;
+        }
+        stats
+    }
+
+    /// Starts or stops logging entities entering and leaving `cell`, at info level under the
+    /// `spatial_index::cells` target.
+    pub fn log_cell(&mut self, cell: Cell, enabled: bool) {
+        if enabled {
+            self.logged_cells.insert(cell);
+        } else {
+            self.logged_cells.remove(&cell);
+        }
+    }
+
+    pub fn is_cell_logged(&self, cell: Cell) -> bool {
+        self.logged_cells.contains(&cell)
+    }
+
+    fn count_query(&self) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The entities in `cell`, with their indexed positions.
+    fn cell(&self, cell: Cell) -> &[(Entity, Vec3)] {
+        match &self.storage {
+            Storage::HashMap(hash_cells) => hash_cells.cells.get(&cell).map_or(&[], Vec::as_slice),
+            Storage::DenseGrid(grid) => grid.cell(cell),
+        }
+    }
+
+    /// Visits every occupied cell with its entities.
+    fn for_each_cell<F: FnMut(Cell, &[(Entity, Vec3)])>(&self, mut handler: F) {
+        match &self.storage {
+            Storage::HashMap(hash_cells) => {
+                for (cell, entities) in &hash_cells.cells {
+                    handler(*cell, entities);
+                }
+            }
+            Storage::DenseGrid(grid) => {
+                for (slot, range) in grid.starts.windows(2).enumerate() {
+                    if range[0] != range[1] {
+                        handler(grid.slot_cell(slot), &grid.entries[range[0] as usize..range[1] as usize]);
+                    }
+                }
+                for (cell, entities) in &grid.outside {
+                    handler(*cell, entities);
+                }
+            }
+        }
+    }
+
+    /// Visits only the entities within `radius` of `pos`, with their indexed position and squared distance.
+    pub fn query_within<F: FnMut(Entity, Vec3, f32)>(&self, pos: Vec3, radius: f32, mut handler: F) {
+        self.count_query();
+        let radius2 = radius * radius;
         self.query_cells(pos, radius, |cell| {
-            if let Some(vec) = self.cells.get(&cell) {
-                for entity in vec {
-                    handler(*entity);
+            for (entity, other) in self.cell(cell) {
+                let dist2 = pos.distance_squared(*other);
+                if dist2 <= radius2 {
+                    handler(*entity, *other, dist2);
+                }
+            }
+        });
+    }
+
+    /// Returns up to `k` entities closest to `pos` and no further away than `max_radius`, with their
+    /// distances, sorted from closest. Candidates are visited in rings of cells around `pos`, and
+    /// those for which `filter` returns false are left out of the results.
+    pub fn query_k_nearest<F: FnMut(Entity) -> bool>(&self, pos: Vec3, k: usize, max_radius: f32, mut filter: F) -> Vec<(Entity, f32)> {
+        self.count_query();
+        let mut nearest: Vec<(Entity, f32)> = Vec::with_capacity(k + 1);
+        if k == 0 {
+            return nearest;
+        }
+        let center = self.calc_cell(pos);
+        let max_ring = (max_radius / self.cell_size).ceil() as i32 + 1;
+        for ring in 0..=max_ring {
+            self.ring_cells(center, ring, |cell| {
+                for (entity, other) in self.cell(cell) {
+                    let dist = pos.distance(*other);
+                    if dist > max_radius || (nearest.len() == k && dist >= nearest[k - 1].1) || !filter(*entity) {
+                        continue;
+                    }
+                    let i = nearest.partition_point(|(_, d)| *d <= dist);
+                    nearest.insert(i, (*entity, dist));
+                    nearest.truncate(k);
+                }
+            });
+            // every cell in the next ring is at least this far away
+            let covered = ring as f32 * self.cell_size;
+            if covered >= max_radius || (nearest.len() == k && nearest[k - 1].1 <= covered) {
+                break;
+            }
+        }
+        nearest
+    }
+
+    /// Returns the closest entity whose bounding sphere the ray from `origin` along `dir` hits within
+    /// `max_dist`, and the distance to where it enters the sphere. `radius` returns each candidate's
+    /// bounding radius, which must not exceed the cell size, or `None` to leave it out.
+    pub fn raycast<F: FnMut(Entity) -> Option<f32>>(&self, origin: Vec3, dir: Vec3, max_dist: f32, radius: F) -> Option<(Entity, f32)> {
+        self.cast(origin, dir, max_dist, radius, true).first().copied()
+    }
+
+    /// Like `raycast`, but returns every entity hit, sorted from closest.
+    pub fn raycast_all<F: FnMut(Entity) -> Option<f32>>(&self, origin: Vec3, dir: Vec3, max_dist: f32, radius: F) -> Vec<(Entity, f32)> {
+        self.cast(origin, dir, max_dist, radius, false)
+    }
+
+    fn cast<F: FnMut(Entity) -> Option<f32>>(&self, origin: Vec3, dir: Vec3, max_dist: f32, mut radius: F, first: bool) -> Vec<(Entity, f32)> {
+        self.count_query();
+        let mut hits: Vec<(Entity, f32)> = Vec::new();
+        let Some(dir) = dir.try_normalize() else {
+            return hits;
+        };
+        let mut visited = HashSet::new();
+        self.traverse_cells(origin, dir, max_dist, |cell, exit| {
+            // spheres no larger than a cell that touch the ray here are centered in a neighbouring cell
+            for ring in 0..=1 {
+                self.ring_cells(cell, ring, |neighbour| {
+                    if !visited.insert(neighbour) {
+                        return;
+                    }
+                    for (entity, pos) in self.cell(neighbour) {
+                        let Some(r) = radius(*entity) else { continue };
+                        match ray_sphere(origin, dir, *pos, r) {
+                            Some(dist) if dist <= max_dist => hits.push((*entity, dist)),
+                            _ => {}
+                        }
+                    }
+                });
+            }
+            // nothing in the cells further along can be hit before the ray leaves this one
+            !(first && hits.iter().any(|(_, dist)| *dist <= exit))
+        });
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+        hits
+    }
+
+    /// Visits the cells the ray passes through in order, with the distance at which it leaves each
+    /// one, until it has gone `max_dist` or `handler` returns false. `dir` must be normalized.
+    fn traverse_cells<F: FnMut(Cell, f32) -> bool>(&self, origin: Vec3, dir: Vec3, max_dist: f32, mut handler: F) {
+        let (x, y, z) = self.calc_cell(origin);
+        let mut cell = [x, y, z];
+        let mut step = [0; 3];
+        // distance along the ray to the next cell boundary on each axis, and between boundaries
+        let mut next = [f32::INFINITY; 3];
+        let mut delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            if dir[axis] == 0.0 || (axis == 1 && !self.volumetric) {
+                continue;
+            }
+            step[axis] = dir[axis].signum() as i32;
+            let boundary = (cell[axis] + (step[axis] > 0) as i32) as f32 * self.cell_size;
+            next[axis] = (boundary - origin[axis]) / dir[axis];
+            delta[axis] = self.cell_size / dir[axis].abs();
+        }
+        loop {
+            let axis = (0..3).min_by(|a, b| next[*a].total_cmp(&next[*b])).unwrap();
+            let exit = next[axis].min(max_dist);
+            if !handler((cell[0], cell[1], cell[2]), exit) || exit >= max_dist {
+                return;
+            }
+            cell[axis] += step[axis];
+            next[axis] += delta[axis];
+        }
+    }
+
+    /// Visits the cells at a Chebyshev distance of exactly `ring` from `center`.
+    fn ring_cells<F: FnMut(Cell)>(&self, center: Cell, ring: i32, mut handler: F) {
+        let (cx, cy, cz) = center;
+        let layer = |y: i32, full: bool, handler: &mut F| {
+            if full || ring == 0 {
+                for z in -ring..=ring {
+                    for x in -ring..=ring {
+                        handler((cx + x, y, cz + z));
+                    }
+                }
+            } else {
+                for x in -ring..=ring {
+                    handler((cx + x, y, cz - ring));
+                    handler((cx + x, y, cz + ring));
+                }
+                for z in (1 - ring)..ring {
+                    handler((cx - ring, y, cz + z));
+                    handler((cx + ring, y, cz + z));
+                }
+            }
+        };
+        if !self.volumetric {
+            layer(cy, false, &mut handler);
+            return;
+        }
+        for y in -ring..=ring {
+            layer(cy + y, y.abs() == ring, &mut handler);
+        }
+    }
+
+    /// Visits the entities inside the axis-aligned box from `min` to `max`, with their indexed positions.
+    pub fn query_rect<F: FnMut(Entity, Vec3)>(&self, min: Vec3, max: Vec3, mut handler: F) {
+        self.count_query();
+        self.query_cell_range(self.calc_cell(min), self.calc_cell(max), |entities| {
+            for (entity, pos) in entities {
+                if pos.cmpge(min).all() && pos.cmple(max).all() {
+                    handler(*entity, *pos);
+                }
+            }
+        });
+    }
+
+    /// Visits the entities inside `frustum`, such as a camera's, with their indexed positions.
+    pub fn query_frustum<F: FnMut(Entity, Vec3)>(&self, frustum: &Frustum, mut handler: F) {
+        self.count_query();
+        self.for_each_cell(|cell, entities| {
+            if !frustum.intersects_obb(&self.cell_bounds(cell), &Affine3A::IDENTITY, true, true) {
+                return;
+            }
+            for (entity, pos) in entities {
+                if frustum.intersects_sphere(&Sphere { center: (*pos).into(), radius: 0.0 }, true) {
+                    handler(*entity, *pos);
                 }
             }
         });
     }
 
+    /// Visits the contents of the occupied cells from `min` to `max` inclusive.
+    fn query_cell_range<F: FnMut(&[(Entity, Vec3)])>(&self, min: Cell, max: Cell, mut handler: F) {
+        let extent = |a: i32, b: i32| (b as i64 - a as i64 + 1).max(0) as u64;
+        let count = extent(min.0, max.0).saturating_mul(extent(min.1, max.1)).saturating_mul(extent(min.2, max.2));
+        if count > self.cell_count() as u64 {
+            // cheaper to go through the occupied cells than every cell in the range
+            self.for_each_cell(|cell, entities| {
+                if (min.0..=max.0).contains(&cell.0) && (min.1..=max.1).contains(&cell.1) && (min.2..=max.2).contains(&cell.2) {
+                    handler(entities);
+                }
+            });
+            return;
+        }
+        for y in min.1..=max.1 {
+            for z in min.2..=max.2 {
+                for x in min.0..=max.0 {
+                    let entities = self.cell((x, y, z));
+                    if !entities.is_empty() {
+                        handler(entities);
+                    }
+                }
+            }
+        }
+    }
+
+    fn cell_bounds(&self, cell: Cell) -> Aabb {
+        let half = self.cell_size * 0.5;
+        let center = Vec3::new(cell.0 as f32, cell.1 as f32, cell.2 as f32) * self.cell_size + half;
+        if self.volumetric {
+            Aabb { center: center.into(), half_extents: Vec3::splat(half).into() }
+        } else {
+            Aabb {
+                center: Vec3::new(center.x, 0.0, center.z).into(),
+                half_extents: Vec3::new(half, COLUMN_HALF_HEIGHT, half).into(),
+            }
+        }
+    }
+
     pub fn query_cells<F: FnMut(Cell)>(&self, pos: Vec3, radius: f32, mut handler: F) {
-        let cx = pos.x / CELL_DIM;
-        let cz = pos.z / CELL_DIM;
-        let r = radius / CELL_DIM;
+        let r = radius / self.cell_size;
+        if !self.volumetric {
+            Self::query_disc_cells(pos / self.cell_size, r, 0, &mut handler);
+            return;
+        }
+
+        let cy = pos.y / self.cell_size;
+        let miny = (cy - r).floor() as i32;
+        let maxy = (cy + r).floor() as i32;
+        for y in miny..=maxy {
+            let ytest = closest_in_cell(y, cy);
+            let ydist2 = (ytest - cy)*(ytest - cy);
+            Self::query_disc_cells(pos / self.cell_size, (r*r - ydist2).sqrt(), y, &mut handler);
+        }
+    }
+
+    /// Visits the cells in layer `y` overlapping the circle around `center` (in cell units) in the xz plane.
+    fn query_disc_cells<F: FnMut(Cell)>(center: Vec3, r: f32, y: i32, handler: &mut F) {
+        let cx = center.x;
+        let cz = center.z;
         let minz = (cz - r).floor() as i32;
-        let maxz = ((cz + r).ceil() as i32) - 1;
+        let maxz = (cz + r).floor() as i32;
 
         for z in minz..=maxz {
-            let ztest = if ((z + 1) as f32) < cz {
-                (z + 1) as f32
-            } else if (z as f32) > cz {
-                z as f32
-            } else {
-                cz
-            };
+            let ztest = closest_in_cell(z, cz);
 
             let zdist2 = (ztest - cz)*(ztest - cz);
             let xdiff = (r*r - zdist2).sqrt();
             let minx = (cx - xdiff).floor() as i32;
-            let maxx = ((cx + xdiff).ceil() as i32) - 1;
+            let maxx = (cx + xdiff).floor() as i32;
 
             for x in minx..=maxx {
-                handler((x, z));
+                handler((x, y, z));
             }
         }
     }
+}
 
-    fn insert(&mut self, cell: Cell, entity: Entity) {
-        self.cells.entry(cell).or_insert_with(|| Vec::new()).push(entity);
-        if cell == (0, 0) {
-            println!("inserted. new size: {}", self.cells.get(&cell).unwrap().len());
+/// Moves entities which have moved into another cell, and updates the positions of the others,
+/// so queries see where entities are as soon as the set has run.
+fn update_spatial_index<T: Component>(
+    mut query: Query<(Entity, &Transform, &mut CellAssociation<T>), Changed<Transform>>,
+    mut index: ResMut<SpatialIndex<T>>
+) {
+    let index = index.as_mut();
+    let Storage::HashMap(hash_cells) = &mut index.storage else {
+        return;
+    };
+    for (entity, transform, mut cell_assoc) in &mut query {
+        let cell = calc_cell(transform.translation, index.cell_size, index.volumetric);
+        if cell_assoc.cell == Some(cell) {
+            hash_cells.set_position(entity, transform.translation);
+        } else {
+            hash_cells.remove(entity);
+            hash_cells.insert(cell, entity, transform.translation);
+            if cell_assoc.cell.is_some() {
+                index.moves += 1;
+            }
+            log_move(&index.logged_cells, entity, cell_assoc.cell, Some(cell));
+            cell_assoc.cell = Some(cell);
         }
     }
+}
 
-    fn remove(&mut self, cell: Cell, entity: Entity) {
-        if let Entry::Occupied(mut occupied) = self.cells.entry(cell) {
-            let vec = occupied.get_mut();
-            vec.retain_mut(|e| *e != entity);
-            if cell == (0, 0) {
-                println!("removed. new size: {}", vec.len());
-            }
-            if vec.len() == 0 {
-                occupied.remove_entry();
+/// Sorts every entity into the dense grid from scratch.
+fn rebuild_spatial_index<T: Component>(
+    mut query: Query<(Entity, &Transform, &mut CellAssociation<T>)>,
+    mut index: ResMut<SpatialIndex<T>>,
+) {
+    let SpatialIndex { storage: Storage::DenseGrid(grid), cell_size, volumetric, logged_cells, moves, .. } = index.as_mut() else {
+        return;
+    };
+    grid.rebuild(query.iter_mut().map(|(entity, transform, mut cell_assoc)| {
+        let cell = calc_cell(transform.translation, *cell_size, *volumetric);
+        if cell_assoc.cell != Some(cell) {
+            if cell_assoc.cell.is_some() {
+                *moves += 1;
             }
+            log_move(logged_cells, entity, cell_assoc.cell, Some(cell));
+            cell_assoc.cell = Some(cell);
         }
+        (entity, transform.translation, cell)
+    }));
+}
+
+/// Rebuilds the dense grid again when entities in it were despawned after it was built, since
+/// they can't be taken out of the packed array.
+fn rebuild_after_despawns<T: Component>(
+    mut removed: RemovedComponents<CellAssociation<T>>,
+    query: Query<(Entity, &Transform, &mut CellAssociation<T>)>,
+    index: ResMut<SpatialIndex<T>>,
+) {
+    if removed.read().count() > 0 {
+        rebuild_spatial_index(query, index);
     }
 }
 
-pub fn update_cell_association(
-    mut commands: Commands,
-    mut query: Query<(Entity, &Transform, &mut CellAssociation), Without<HasDirtyCell>>,
+/// Removes entities which were despawned, or lost their `CellAssociation`, since the last run.
+fn remove_despawned_from_index<T: Component>(
+    mut removed: RemovedComponents<CellAssociation<T>>,
+    mut index: ResMut<SpatialIndex<T>>,
 ) {
-    for (entity, transform, mut cell_assoc) in &mut query {
-        cell_assoc.new_cell = calc_cell(transform.translation);
-        if cell_assoc.new_cell != cell_assoc.cell {
-            commands.entity(entity).insert(HasDirtyCell);
+    let index = index.as_mut();
+    if let Storage::HashMap(hash_cells) = &mut index.storage {
+        for entity in removed.read() {
+            let cell = hash_cells.remove(entity);
+            log_move(&index.logged_cells, entity, cell, None);
         }
     }
 }
 
-pub fn update_spatial_index(
-    mut commands: Commands,
-    mut query: Query<(Entity, &mut CellAssociation), With<HasDirtyCell>>,
-    mut index: ResMut<SpatialIndex>
-) {
-    for (entity, mut cell_assoc) in &mut query {
-        index.remove(cell_assoc.cell, entity);
-        index.insert(cell_assoc.new_cell, entity);
-        cell_assoc.cell = cell_assoc.new_cell;
-        commands.entity(entity).remove::<HasDirtyCell>();
+/// Logs `entity` leaving `from` and entering `to`, for the cells that are logged. Only the hash map
+/// backend knows where despawned entities were, so the dense grid doesn't report them leaving.
+fn log_move(logged_cells: &HashSet<Cell>, entity: Entity, from: Option<Cell>, to: Option<Cell>) {
+    if let Some(from) = from.filter(|cell| logged_cells.contains(cell)) {
+        info!(target: CELL_LOG_TARGET, "{:?} left cell {:?}", entity, from);
+    }
+    if let Some(to) = to.filter(|cell| logged_cells.contains(cell)) {
+        info!(target: CELL_LOG_TARGET, "{:?} entered cell {:?}", entity, to);
+    }
+}
+
+/// Paths of the diagnostics recorded for `SpatialIndex<T>`, named after `T`, such as
+/// `spatial_index/Ship/cells`.
+#[derive(Resource)]
+struct SpatialIndexDiagnostics<T: Component> {
+    cell_count: DiagnosticPath,
+    max_occupancy: DiagnosticPath,
+    mean_occupancy: DiagnosticPath,
+    moves: DiagnosticPath,
+    queries: DiagnosticPath,
+    marker: PhantomData<T>,
+}
+
+impl<T: Component> SpatialIndexDiagnostics<T> {
+    fn new() -> Self {
+        let name = get_short_name(type_name::<T>());
+        let path = |stat: &str| DiagnosticPath::from_components([DIAGNOSTIC_PREFIX, &name, stat]);
+        Self {
+            cell_count: path("cells"),
+            max_occupancy: path("max_occupancy"),
+            mean_occupancy: path("mean_occupancy"),
+            moves: path("moves"),
+            queries: path("queries"),
+            marker: PhantomData,
+        }
+    }
+
+    fn paths(&self) -> [&DiagnosticPath; 5] {
+        [&self.cell_count, &self.max_occupancy, &self.mean_occupancy, &self.moves, &self.queries]
     }
 }
 
-pub fn test_spatial_index(
-    transforms: Query<&Transform>,
-    cursor: Res<CursorPosition>,
-    index: Res<SpatialIndex>,
-    mut gizmos: Gizmos
+/// Records the stats of the frame as diagnostics, and starts counting moves and queries afresh.
+fn record_spatial_index_stats<T: Component>(
+    mut index: ResMut<SpatialIndex<T>>,
+    paths: Res<SpatialIndexDiagnostics<T>>,
+    mut diagnostics: Diagnostics,
 ) {
-    let radius = 5.0;
-    gizmos.circle(cursor.position, Direction3d::Y, radius, Color::RED);
+    let stats = index.stats();
+    diagnostics.add_measurement(&paths.cell_count, || stats.cell_count as f64);
+    diagnostics.add_measurement(&paths.max_occupancy, || stats.max_occupancy as f64);
+    diagnostics.add_measurement(&paths.mean_occupancy, || stats.mean_occupancy as f64);
+    diagnostics.add_measurement(&paths.moves, || stats.moves as f64);
+    diagnostics.add_measurement(&paths.queries, || stats.queries as f64);
+    index.moves = 0;
+    *index.queries.get_mut() = 0;
+}
 
-    index.query_cells(cursor.position, radius, |cell| {
-        gizmos.rect(Vec3::new((cell.0 as f32)*CELL_DIM+CELL_DIM*0.5, 0.0, (cell.1 as f32)*CELL_DIM+CELL_DIM*0.5), Quat::from_axis_angle(Vec3::X, PI*0.5), Vec2::new(CELL_DIM, CELL_DIM), Color::WHITE);
+/// Verifies that every entity with a `CellAssociation<T>` is indexed, in the cell and with the
+/// position matching its `Transform`, and that nothing else is.
+fn check_spatial_index<T: Component>(
+    index: Res<SpatialIndex<T>>,
+    query: Query<(&Transform, &CellAssociation<T>)>,
+) {
+    let mut count = 0;
+    index.for_each_cell(|cell, entities| {
+        for (slot, (entity, pos)) in entities.iter().enumerate() {
+            count += 1;
+            let Ok((transform, cell_assoc)) = query.get(*entity) else {
+                error!("spatial index: {:?} in cell {:?} no longer exists", entity, cell);
+                continue;
+            };
+            // only the hash map backend keeps track of where each entity is stored
+            if let Storage::HashMap(hash_cells) = &index.storage {
+                if hash_cells.entity_cells.get(entity) != Some(&(cell, slot)) {
+                    error!("spatial index: {:?} is in slot {} of cell {:?}, but recorded at {:?}",
+                        entity, slot, cell, hash_cells.entity_cells.get(entity));
+                }
+            }
+            if cell_assoc.cell != Some(cell) {
+                error!("spatial index: {:?} is in cell {:?}, but associated with {:?}", entity, cell, cell_assoc.cell);
+            }
+            if index.calc_cell(transform.translation) != cell {
+                error!("spatial index: {:?} is in cell {:?}, but its position {} is in {:?}",
+                    entity, cell, transform.translation, index.calc_cell(transform.translation));
+            }
+            if *pos != transform.translation {
+                error!("spatial index: {:?} is stored at {}, but is at {}", entity, pos, transform.translation);
+            }
+        }
     });
+    let indexed = match &index.storage {
+        Storage::HashMap(hash_cells) => hash_cells.entity_cells.len(),
+        Storage::DenseGrid(_) => count,
+    };
+    if count != indexed || count != query.iter().len() {
+        error!("spatial index: {} entries for {} indexed and {} associated entities",
+            count, indexed, query.iter().len());
+    }
+}
+
+/// Shows the diagnostics of every spatial index in the top right corner. F3 toggles it.
+pub struct SpatialIndexReadoutPlugin;
+
+impl Plugin for SpatialIndexReadoutPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_readout).add_systems(
+            Update,
+            (
+                toggle_readout.run_if(bevy::input::common_conditions::input_just_pressed(KeyCode::F3)),
+                update_readout,
+            ),
+        );
+    }
+}
+
+#[derive(Component)]
+struct SpatialIndexReadout;
 
-    index.query(cursor.position, radius, |entity| {
-        if let Ok(transform) = transforms.get(entity) {
-            if (transform.translation - cursor.position).length() < radius {
-                gizmos.circle(transform.translation, Direction3d::Y, 1.0, Color::RED);
+fn spawn_readout(mut commands: Commands) {
+    commands.spawn((
+        SpatialIndexReadout,
+        TextBundle::from_section("", TextStyle { font_size: 16.0, ..default() }).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..default()
+        }),
+    ));
+}
+
+fn toggle_readout(mut query: Query<&mut Visibility, With<SpatialIndexReadout>>) {
+    for mut visibility in &mut query {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn update_readout(store: Res<DiagnosticsStore>, mut query: Query<&mut Text, With<SpatialIndexReadout>>) {
+    let mut lines: Vec<String> = store
+        .iter()
+        .filter(|diagnostic| diagnostic.path().components().next() == Some(DIAGNOSTIC_PREFIX))
+        .map(|diagnostic| {
+            let path = diagnostic.path().as_str().trim_start_matches(DIAGNOSTIC_PREFIX).trim_start_matches('/');
+            format!("{}: {:.1}", path, diagnostic.smoothed().unwrap_or_default())
+        })
+        .collect();
+    lines.sort();
+    for mut text in &mut query {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::camera::CameraProjection;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[derive(Component)]
+    struct Marker;
+
+    const CELL_SIZE: f32 = 10.0;
+
+    struct TestIndex {
+        name: String,
+        index: SpatialIndex<Marker>,
+        entities: Vec<(Entity, Vec3)>,
+    }
+
+    /// An index of scattered points with each backend, flat and volumetric, with the points in it.
+    /// The dense grids cover only part of the points, so the entities outside them are tested too.
+    fn indices() -> Vec<TestIndex> {
+        let mut indices = Vec::new();
+        for volumetric in [false, true] {
+            let mut rng = StdRng::seed_from_u64(1);
+            let mut points: Vec<Vec3> = (0..500)
+                .map(|_| {
+                    let y = if volumetric { rng.gen_range(-50.0..50.0) } else { 0.0 };
+                    Vec3::new(rng.gen_range(-100.0..100.0), y, rng.gen_range(-100.0..100.0))
+                })
+                .collect();
+            // on cell boundaries, and sharing a position
+            points.extend([Vec3::ZERO, Vec3::ZERO, Vec3::new(-CELL_SIZE, 0.0, CELL_SIZE), Vec3::new(CELL_SIZE * 3.0, 0.0, -CELL_SIZE * 2.0)]);
+            let entities: Vec<(Entity, Vec3)> = points.into_iter().enumerate().map(|(i, pos)| (Entity::from_raw(i as u32), pos)).collect();
+
+            let backends = [
+                SpatialIndexBackend::HashMap,
+                SpatialIndexBackend::DenseGrid { min: Vec3::new(-60.0, -30.0, -60.0), max: Vec3::new(60.0, 30.0, 60.0) },
+            ];
+            for backend in backends {
+                let mut index = SpatialIndex::new(CELL_SIZE, volumetric, backend);
+                let cells: Vec<(Entity, Vec3, Cell)> = entities.iter().map(|(entity, pos)| (*entity, *pos, index.calc_cell(*pos))).collect();
+                match &mut index.storage {
+                    Storage::HashMap(hash_cells) => {
+                        for (entity, pos, cell) in cells {
+                            hash_cells.insert(cell, entity, pos);
+                        }
+                    }
+                    Storage::DenseGrid(grid) => grid.rebuild(cells.into_iter()),
+                }
+                let name = format!("{:?}, {}", backend, if volumetric { "volumetric" } else { "flat" });
+                indices.push(TestIndex { name, index, entities: entities.clone() });
             }
         }
-    });
+        indices
+    }
+
+    /// Points to query around, inside and outside the points and the dense grids.
+    fn query_points(volumetric: bool) -> Vec<Vec3> {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut points: Vec<Vec3> = (0..50)
+            .map(|_| {
+                let y = if volumetric { rng.gen_range(-70.0..70.0) } else { 0.0 };
+                Vec3::new(rng.gen_range(-130.0..130.0), y, rng.gen_range(-130.0..130.0))
+            })
+            .collect();
+        points.extend([Vec3::ZERO, Vec3::new(CELL_SIZE, 0.0, -CELL_SIZE)]);
+        points
+    }
+
+    /// Bounding radii of up to the cell size, with some entities left out of raycasts.
+    fn radius(entity: Entity) -> Option<f32> {
+        (!entity.index().is_multiple_of(7)).then(|| 1.0 + (entity.index() % 10) as f32)
+    }
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn query_within_matches_brute_force() {
+        for TestIndex { name, index, entities } in indices() {
+            for center in query_points(index.volumetric) {
+                for radius in [0.0, 4.0, CELL_SIZE, 27.5, 80.0] {
+                    let mut found = Vec::new();
+                    index.query_within(center, radius, |entity, pos, dist2| {
+                        assert_eq!(dist2, pos.distance_squared(center));
+                        found.push(entity);
+                    });
+                    let expected: Vec<Entity> = entities.iter()
+                        .filter(|(_, pos)| pos.distance_squared(center) <= radius * radius)
+                        .map(|(entity, _)| *entity)
+                        .collect();
+                    assert_eq!(sorted(found), sorted(expected), "{}: within {} of {}", name, radius, center);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn query_k_nearest_matches_brute_force() {
+        let filter = |entity: Entity| !entity.index().is_multiple_of(3);
+        for TestIndex { name, index, entities } in indices() {
+            for center in query_points(index.volumetric) {
+                for (k, max_radius) in [(1, 30.0), (5, 30.0), (12, 200.0), (600, 45.0)] {
+                    let found = index.query_k_nearest(center, k, max_radius, filter);
+                    let mut expected: Vec<(Entity, f32)> = entities.iter()
+                        .map(|(entity, pos)| (*entity, center.distance(*pos)))
+                        .filter(|(entity, dist)| *dist <= max_radius && filter(*entity))
+                        .collect();
+                    expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+                    expected.truncate(k);
+                    let distances = |hits: &[(Entity, f32)]| hits.iter().map(|(_, dist)| *dist).collect::<Vec<_>>();
+                    // entities at the same distance may come in either order
+                    assert_eq!(distances(&found), distances(&expected), "{}: {} nearest to {}", name, k, center);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn raycasts_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for TestIndex { name, index, entities } in indices() {
+            for origin in query_points(index.volumetric) {
+                let y = if index.volumetric { rng.gen_range(-1.0..1.0) } else { 0.0 };
+                let dir = Vec3::new(rng.gen_range(-1.0..1.0), y, rng.gen_range(-1.0..1.0));
+                for dir in [dir, Vec3::X, Vec3::NEG_Z] {
+                    let max_dist = 150.0;
+                    let all = index.raycast_all(origin, dir, max_dist, radius);
+                    let mut expected: Vec<(Entity, f32)> = entities.iter()
+                        .filter_map(|(entity, pos)| {
+                            let dist = ray_sphere(origin, dir.normalize(), *pos, radius(*entity)?)?;
+                            (dist <= max_dist).then_some((*entity, dist))
+                        })
+                        .collect();
+                    expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+                    let distances = |hits: &[(Entity, f32)]| hits.iter().map(|(_, dist)| *dist).collect::<Vec<_>>();
+                    assert_eq!(distances(&all), distances(&expected), "{}: raycast_all from {} along {}", name, origin, dir);
+                    assert_eq!(sorted(all.iter().map(|(entity, _)| *entity).collect()), sorted(expected.iter().map(|(entity, _)| *entity).collect()));
+
+                    let first = index.raycast(origin, dir, max_dist, radius);
+                    assert_eq!(first.map(|(_, dist)| dist), expected.first().map(|(_, dist)| *dist), "{}: raycast from {} along {}", name, origin, dir);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn query_rect_matches_brute_force() {
+        for TestIndex { name, index, entities } in indices() {
+            let points = query_points(index.volumetric);
+            for (a, b) in points.iter().zip(points.iter().rev()) {
+                let (min, max) = (a.min(*b), a.max(*b));
+                let mut found = Vec::new();
+                index.query_rect(min, max, |entity, _| found.push(entity));
+                let expected: Vec<Entity> = entities.iter()
+                    .filter(|(_, pos)| pos.cmpge(min).all() && pos.cmple(max).all())
+                    .map(|(entity, _)| *entity)
+                    .collect();
+                assert_eq!(sorted(found), sorted(expected), "{}: rect from {} to {}", name, min, max);
+            }
+        }
+    }
+
+    #[test]
+    fn query_frustum_matches_brute_force() {
+        for TestIndex { name, index, entities } in indices() {
+            let points = query_points(index.volumetric);
+            for (eye, target) in points.iter().zip(points.iter().skip(1)) {
+                let eye = *eye + Vec3::Y * 50.0;
+                let view = Transform::from_translation(eye).looking_at(*target, Vec3::Y).compute_matrix();
+                let projection = PerspectiveProjection { far: 150.0, ..default() }.get_projection_matrix();
+                let frustum = Frustum::from_view_projection(&(projection * view.inverse()));
+                let mut found = Vec::new();
+                index.query_frustum(&frustum, |entity, _| found.push(entity));
+                let expected: Vec<Entity> = entities.iter()
+                    .filter(|(_, pos)| frustum.intersects_sphere(&Sphere { center: (*pos).into(), radius: 0.0 }, true))
+                    .map(|(entity, _)| *entity)
+                    .collect();
+                assert_eq!(sorted(found), sorted(expected), "{}: frustum from {} towards {}", name, eye, target);
+            }
+        }
+    }
 }